@@ -5,39 +5,166 @@
 //! @author jasmith79
 //! @license MIT
 //! @copyright 2023
-use clap::{Arg, ArgAction, Command};
+use clap::builder::PossibleValuesParser;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::env;
+use std::ffi::OsString;
 use std::path::PathBuf;
 use std::process;
 
+use crate::config::{default_config_path, profile_args, read_profile};
+
+use playlistrs::{
+    drive_of, portable_path, read_rewrite_file, DuplicateMode, ExportJob, ExportOptions,
+    ExtensionRule, FolderMode, FsProfile, Log, MissingMode, Normalization, PlaylistKind,
+    PlaylisterError, RelocateError, RewriteError, RewriteRule, SmartFormat, WriterRegistry,
+};
+
 static MUSIC_PATH_HELP: &str = "Optional path to music files, this will replace the iTunes®\n\
 path to your media files. For example, if you have a music\n\
 file on your Mac at \n\
 /Users/YourName/Music/Music/media.localized/SomeAlbum/SomeSong.m4a\n\
 then this path if present will replace everything before\n\
-'SomeAlbum'.";
+'SomeAlbum'. Any --rewrite rules are tried first. A Windows\n\
+path like D:\\Music makes the playlists use Windows paths.";
+
+static REWRITE_HELP: &str = "Rewrite track paths starting with FROM to start with TO\n\
+instead, written as 'FROM => TO'. Can be given more than once,\n\
+rules are tried in order and the first match wins.";
+
+static REWRITE_REGEX_HELP: &str = "Like --rewrite but FROM is a regular expression and TO can\n\
+refer to its capture groups as $1, $2 etc.";
+
+static REWRITE_FILE_HELP: &str = "Read rewrite rules from a file, one 'FROM => TO' rule per\n\
+line, prefix FROM with 'regex:' for a regular expression.\n\
+These are tried before rules given on the command line.";
+
+static EXT_MAP_HELP: &str = "Swap a file extension after the paths are rewritten, written\n\
+as FROM=TO, e.g. m4a=opus for a transcoded mirror of the\n\
+library. Can be given more than once, the first match wins.";
+
+static EXT_MAP_IF_EXISTS_HELP: &str = "Only swap an extension with --ext-map when the file with the\n\
+new extension exists, e.g. for a partially transcoded mirror.";
 
 static FILE_URL_HELP: &str = "By default Playlister will output plain file paths. However,\n\
 some applications like VLC expect/work better with file\n\
 urls.";
 
+static RELATIVE_HELP: &str = "Write each entry relative to the directory of the playlist\n\
+file it is in, for players that mount the music at different\n\
+locations. Applied after --music-path and --rewrite. XSPF\n\
+and JSPF entries are written as relative URIs.";
+
+static RELATIVE_BASE_HELP: &str = "Make --relative entries relative to this absolute directory\n\
+instead of the output path, e.g. where the playlists will be\n\
+copied to on the device. Mirrored folders are added to it.";
+
 static DEFAULT_LIST_HELP: &str = "By default iTunes/Music will create a number of playlists,\n\
 like Downloaded and playlister will ignore them unless you\n\
 pass this flag.";
 
+static INCLUDE_KIND_HELP: &str = "Include a specific kind of built-in playlist, can be given\n\
+more than once. Ignored with --include-default-playlists.";
+
+static FORMAT_HELP: &str = "The playlist format to write. m3u8 writes UTF-8 extended m3u\n\
+files with album, artist, genre and artwork directives, xspf\n\
+writes XML Shareable Playlist Format files and pls writes\n\
+Winamp style PLS files, wpl writes Windows Media Player\n\
+playlists, jspf writes JSON XSPF and json writes a dump of\n\
+the full playlist and track data.";
+
+static WINDOWS_DRIVE_HELP: &str = "Write Windows style paths rooted at this drive letter, e.g.\n\
+with D the path /Music/SomeAlbum/SomeSong.m4a becomes\n\
+D:\\Music\\SomeAlbum\\SomeSong.m4a. Combine with --music-path to\n\
+relocate the library first.";
+
+static FOLDERS_HELP: &str = "How to handle iTunes® playlist folders. mirror writes\n\
+playlists into subdirectories matching the folders, flatten\n\
+writes everything to the output path including the merged\n\
+playlists for the folders themselves, skip writes everything\n\
+to the output path without the folder playlists.";
+
+static FILESYSTEM_HELP: &str = "The filesystem the playlists will be copied to. Characters\n\
+and names it doesn't allow in file and folder names are\n\
+replaced and long names are shortened.";
+
+static DUPLICATES_HELP: &str = "What to do with playlists that would be written to the same\n\
+file, e.g. two playlists called Mix in different folders with\n\
+--folders flatten. suffix numbers them like Mix (2), folder\n\
+prefixes the names of the folders they're in like Rock - Mix\n\
+and error writes nothing and exits with status 12.";
+
+static NORMALIZE_HELP: &str = "Unicode normalization of the paths, file names and titles\n\
+written out. nfc composes accents like most players and Linux\n\
+expect, nfd decomposes them like macOS and some Samba shares\n\
+store them, none writes them as the library has them and ascii\n\
+strips them, e.g. Café -> Cafe.";
+
+static CHECK_SMART_HELP: &str = "Recompute smart playlists from their rules and report any\n\
+differences from the tracks iTunes® exported for them. Ones\n\
+that aren't live updating are skipped.";
+
+static SMART_RULES_HELP: &str = "Write smart playlists as rules instead of track lists, either\n\
+Kodi .xsp or Navidrome .nsp files. Smart playlists using\n\
+criteria the player can't express fall back to --format.";
+
+static VERIFY_HELP: &str = "Check that every track exists at its resolved location and\n\
+report the missing ones per playlist. Exits with status 10 if\n\
+any are missing. m3u8 playlists also get artwork found next to\n\
+the tracks.";
+
+static MISSING_HELP: &str = "What to do with missing tracks when verifying. keep writes\n\
+them anyway, drop leaves them out and comment writes them as\n\
+comments in m3u, xspf and wpl playlists and drops them from\n\
+the formats without comments.";
+
+static RELOCATE_HELP: &str = "Look for tracks that aren't at their resolved location in\n\
+this directory, by path ignoring case, by file name and by\n\
+artist, album and title. Defaults to --music-path.";
+
+static RELOCATION_MAP_HELP: &str = "A tab separated map of library locations to relocated paths.\n\
+Entries in it are used before searching and every relocation\n\
+from this run is written back to it for review. Low confidence\n\
+matches are written commented out.";
+
+static PROFILE_HELP: &str = "Use the options from this profile in the config file. Options\n\
+given on the command line replace the profile's.";
+
+static CONFIG_HELP: &str = "The TOML config file with the profiles, one [profiles.NAME]\n\
+table each with the long option names as keys. Defaults to\n\
+playlistrs/config.toml in $XDG_CONFIG_HOME or ~/.config.";
+
 static ABOUT: &str = "Converts iTunes® playlists into universal format used by\n\
 most music players.";
 
-#[derive(Debug)]
+static EXIT_CODES_HELP: &str = "Exit codes:\n\
+  1   the library couldn't be read\n\
+  2   the arguments are invalid\n\
+  3   no track has a location to work out --music-path from\n\
+  4   the library path isn't a file\n\
+  5   the library path has no parent directory\n\
+  6   the output path isn't a directory\n\
+  7   --windows-drive isn't a drive letter\n\
+  8   a --rewrite or --ext-map rule is invalid\n\
+  9   --relative-base isn't absolute\n\
+  10  --verify found missing tracks\n\
+  11  --relocate couldn't index or write its map\n\
+  12  --duplicates error found playlists with the same name\n\
+  13  a playlist couldn't be written\n\
+  14  the config file or --profile is invalid";
+
+/// What the run exits with when the arguments can't be parsed, none of
+/// the errors use it.
+static USAGE_EXIT_CODE: i32 = 2;
+
+#[derive(Debug, Default)]
 pub struct PlaylisterArgs {
     pub path: PathBuf,
-    pub output_path: PathBuf,
-    pub music_path: Option<PathBuf>,
-    pub verbose: u8,
-    pub use_file_url: bool,
-    pub include_default_playlists: bool,
+    pub format: String,
+    pub job: ExportJob,
 }
 
-fn arg_parser_factory() -> Command {
+fn arg_parser_factory(formats: Vec<&'static str>) -> Command {
     let file_path = Arg::new("file_path")
         .help("The path to your iTunes® library.xml")
         .required(true);
@@ -47,6 +174,34 @@ fn arg_parser_factory() -> Command {
         .short('m')
         .long("music-path");
 
+    let rewrite = Arg::new("rewrite")
+        .help(REWRITE_HELP)
+        .long("rewrite")
+        .value_name("FROM => TO")
+        .action(ArgAction::Append);
+
+    let rewrite_regex = Arg::new("rewrite_regex")
+        .help(REWRITE_REGEX_HELP)
+        .long("rewrite-regex")
+        .value_name("FROM => TO")
+        .action(ArgAction::Append);
+
+    let rewrite_file = Arg::new("rewrite_file")
+        .help(REWRITE_FILE_HELP)
+        .long("rewrite-file");
+
+    let ext_map = Arg::new("ext_map")
+        .help(EXT_MAP_HELP)
+        .long("ext-map")
+        .value_name("FROM=TO")
+        .action(ArgAction::Append);
+
+    let ext_map_if_exists = Arg::new("ext_map_if_exists")
+        .help(EXT_MAP_IF_EXISTS_HELP)
+        .long("ext-map-if-exists")
+        .requires("ext_map")
+        .action(ArgAction::SetTrue);
+
     let output_path = Arg::new("output_path")
         .help("Path to write playlist files to. Defaults to the xml path")
         .short('o')
@@ -57,11 +212,121 @@ fn arg_parser_factory() -> Command {
         .long("use-file-url")
         .action(ArgAction::SetTrue);
 
+    let relative = Arg::new("relative")
+        .help(RELATIVE_HELP)
+        .long("relative")
+        .conflicts_with("use_file_url")
+        .action(ArgAction::SetTrue);
+
+    let relative_base = Arg::new("relative_base")
+        .help(RELATIVE_BASE_HELP)
+        .long("relative-base")
+        .requires("relative");
+
     let include_default_playlists = Arg::new("include_default_playlists")
         .help(DEFAULT_LIST_HELP)
         .long("include-default-playlists")
         .action(ArgAction::SetTrue);
 
+    let include_kind = Arg::new("include_kind")
+        .help(INCLUDE_KIND_HELP)
+        .long("include-kind")
+        .value_parser([
+            "library",
+            "music",
+            "movies",
+            "tv-shows",
+            "podcasts",
+            "audiobooks",
+            "purchased",
+            "genius",
+            "itunes-u",
+            "music-videos",
+            "home-videos",
+            "downloaded",
+            "hidden",
+            "other",
+        ])
+        .action(ArgAction::Append);
+
+    let format = Arg::new("format")
+        .help(FORMAT_HELP)
+        .short('f')
+        .long("format")
+        .value_parser(PossibleValuesParser::new(formats))
+        .default_value("m3u");
+
+    let windows_drive = Arg::new("windows_drive")
+        .help(WINDOWS_DRIVE_HELP)
+        .long("windows-drive");
+
+    let folders = Arg::new("folders")
+        .help(FOLDERS_HELP)
+        .long("folders")
+        .value_parser(["mirror", "flatten", "skip"])
+        .default_value("mirror");
+
+    let filesystem = Arg::new("filesystem")
+        .help(FILESYSTEM_HELP)
+        .long("filesystem")
+        .value_parser(["ext4", "fat32", "exfat", "ntfs", "android"])
+        .default_value("ext4");
+
+    let duplicates = Arg::new("duplicates")
+        .help(DUPLICATES_HELP)
+        .long("duplicates")
+        .value_parser(["suffix", "folder", "error"])
+        .default_value("suffix");
+
+    let normalize = Arg::new("normalize")
+        .help(NORMALIZE_HELP)
+        .long("normalize")
+        .value_parser(["nfc", "nfd", "none", "ascii"])
+        .default_value("nfc");
+
+    let check_smart = Arg::new("check_smart")
+        .help(CHECK_SMART_HELP)
+        .long("check-smart")
+        .action(ArgAction::SetTrue);
+
+    let smart_rules = Arg::new("smart_rules")
+        .help(SMART_RULES_HELP)
+        .long("smart-rules")
+        .value_parser(["kodi", "navidrome"]);
+
+    let verify = Arg::new("verify")
+        .help(VERIFY_HELP)
+        .long("verify")
+        .action(ArgAction::SetTrue);
+
+    let missing = Arg::new("missing")
+        .help(MISSING_HELP)
+        .long("missing")
+        .value_parser(["keep", "drop", "comment"])
+        .default_value("keep");
+
+    let relocate = Arg::new("relocate")
+        .help(RELOCATE_HELP)
+        .long("relocate")
+        .value_name("DIR")
+        .num_args(0..=1);
+
+    let relocation_map = Arg::new("relocation_map")
+        .help(RELOCATION_MAP_HELP)
+        .long("relocation-map")
+        .requires("relocate");
+
+    let profile = Arg::new("profile")
+        .help(PROFILE_HELP)
+        .long("profile")
+        .value_name("NAME");
+
+    let config = Arg::new("config")
+        .help(CONFIG_HELP)
+        .long("config")
+        .value_name("FILE")
+        .requires("profile");
+
     let verbose = Arg::new("verbose")
         .help("verbose output.")
         .short('v')
@@ -72,40 +337,88 @@ fn arg_parser_factory() -> Command {
         .version("0.2.0")
         .author("Jared Smith <jasmith79@gmail.com>")
         .about(ABOUT)
+        .after_help(EXIT_CODES_HELP)
         .arg(file_path)
         .arg(music_path)
+        .arg(rewrite)
+        .arg(rewrite_regex)
+        .arg(rewrite_file)
+        .arg(ext_map)
+        .arg(ext_map_if_exists)
         .arg(output_path)
         .arg(use_file_url)
+        .arg(relative)
+        .arg(relative_base)
         .arg(include_default_playlists)
+        .arg(include_kind)
+        .arg(format)
+        .arg(windows_drive)
+        .arg(folders)
+        .arg(filesystem)
+        .arg(duplicates)
+        .arg(normalize)
+        .arg(check_smart)
+        .arg(smart_rules)
+        .arg(verify)
+        .arg(missing)
+        .arg(relocate)
+        .arg(relocation_map)
+        .arg(profile)
+        .arg(config)
         .arg(verbose)
 }
 
-fn parse_and_validate(matcher: Command) -> PlaylisterArgs {
-    let args = matcher.get_matches();
+fn parse_drive_letter(drive: &str) -> Option<char> {
+    let mut chars = drive.trim_end_matches(':').chars();
+    match (chars.next(), chars.next()) {
+        (Some(letter), None) if letter.is_ascii_alphabetic() => Some(letter.to_ascii_uppercase()),
+        _ => None,
+    }
+}
+
+fn parse_rewrite_rules(args: &ArgMatches) -> Result<Vec<RewriteRule>, RewriteError> {
+    let mut rules = match args.try_get_one::<String>("rewrite_file").ok().flatten() {
+        Some(path) => read_rewrite_file(path)?,
+        None => Vec::new(),
+    };
+
+    // Keep the command line order across both flags.
+    let mut specs = Vec::new();
+    for (id, regex) in [("rewrite", false), ("rewrite_regex", true)] {
+        if let (Some(values), Some(indices)) = (args.get_many::<String>(id), args.indices_of(id)) {
+            specs.extend(indices.zip(values).map(|(index, spec)| (index, spec, regex)));
+        }
+    }
+
+    specs.sort_by_key(|(index, _, _)| *index);
+    for (_, spec, regex) in specs {
+        rules.push(RewriteRule::parse(spec, regex)?);
+    }
+
+    Ok(rules)
+}
+
+fn parse_and_validate(args: ArgMatches) -> Result<PlaylisterArgs, PlaylisterError> {
     let path = args
         .get_one::<String>("file_path")
         .map(PathBuf::from)
         .expect("Must include a path to a Library XML file");
 
     if !path.is_file() {
-        eprintln!("The supplied path must point to a valid iTunes® xml library file");
-        process::exit(3)
+        return Err(PlaylisterError::InputFile(path));
     }
 
     // This shouldn't fail for a valid file...
     let input_dir = path
         .parent()
-        .unwrap_or_else(|| {
-            eprintln!("Input directory invalid.");
-            process::exit(4);
-        })
+        .ok_or_else(|| PlaylisterError::InputDir(path.clone()))?
         .to_path_buf();
 
     let mpath = args
         .try_get_one::<String>("music_path")
         .ok()
         .flatten()
-        .map(PathBuf::from);
+        .map(|mpath| portable_path(mpath));
 
     let opath = args
         .try_get_one::<String>("output_path")
@@ -115,31 +428,235 @@ fn parse_and_validate(matcher: Command) -> PlaylisterArgs {
         .unwrap_or(input_dir);
 
     if !opath.is_dir() {
-        eprintln!("Output path must be a valid directory.");
-        process::exit(5);
+        return Err(PlaylisterError::OutputDir(opath));
     }
 
+    let rewrite_rules = parse_rewrite_rules(&args).map_err(PlaylisterError::Rule)?;
+
+    let extension_rules = args
+        .get_many::<String>("ext_map")
+        .map(|specs| specs.map(|spec| ExtensionRule::parse(spec)).collect())
+        .unwrap_or_else(|| Ok(Vec::new()))
+        .map_err(PlaylisterError::Rule)?;
+    let extension_if_exists = args.get_flag("ext_map_if_exists");
+
     let verbose = args.get_count("verbose");
     let use_file_url = args.get_flag("use_file_url");
+    let relative = args.get_flag("relative");
+    let relative_base = args
+        .try_get_one::<String>("relative_base")
+        .ok()
+        .flatten()
+        .map(|base| portable_path(base));
+
+    if relative_base
+        .as_ref()
+        .is_some_and(|base| !base.is_absolute() && drive_of(base).is_none())
+    {
+        return Err(PlaylisterError::RelativeBase(relative_base.unwrap_or_default()));
+    }
     let include_default_playlists = args.get_flag("include_default_playlists");
+    let check_smart = args.get_flag("check_smart");
+    let smart_rules = args
+        .try_get_one::<String>("smart_rules")
+        .ok()
+        .flatten()
+        .and_then(|f| SmartFormat::from_arg(f));
+
+    let verify = args.get_flag("verify");
+    let missing = args
+        .get_one::<String>("missing")
+        .and_then(|m| MissingMode::from_arg(m))
+        .unwrap_or_default();
+
+    // --relocate on its own searches the music path.
+    let relocate = match args.value_source("relocate") {
+        Some(_) => Some(
+            args.get_one::<String>("relocate")
+                .map(PathBuf::from)
+                .or_else(|| mpath.clone())
+                .ok_or_else(|| {
+                    PlaylisterError::Relocate(RelocateError(String::from(
+                        "--relocate needs a directory or --music-path.",
+                    )))
+                })?,
+        ),
+        None => None,
+    };
+
+    if relocate.as_ref().is_some_and(|dir| !dir.is_dir()) {
+        return Err(PlaylisterError::Relocate(RelocateError(String::from(
+            "Relocation directory must be a valid directory.",
+        ))));
+    }
+
+    let relocation_map = args
+        .try_get_one::<String>("relocation_map")
+        .ok()
+        .flatten()
+        .map(PathBuf::from);
+
+    let include_kinds = args
+        .get_many::<String>("include_kind")
+        .map(|kinds| kinds.filter_map(|k| PlaylistKind::from_arg(k)).collect())
+        .unwrap_or_default();
+
+    let format = args
+        .get_one::<String>("format")
+        .cloned()
+        .unwrap_or_default();
+
+    let folders = args
+        .get_one::<String>("folders")
+        .and_then(|f| FolderMode::from_arg(f))
+        .unwrap_or_default();
+
+    let filesystem = args
+        .get_one::<String>("filesystem")
+        .and_then(|f| FsProfile::from_arg(f))
+        .unwrap_or_default();
+
+    let duplicates = args
+        .get_one::<String>("duplicates")
+        .and_then(|d| DuplicateMode::from_arg(d))
+        .unwrap_or_default();
+
+    let normalization = args
+        .get_one::<String>("normalize")
+        .and_then(|n| Normalization::from_arg(n))
+        .unwrap_or_default();
+
+    let windows_drive = args
+        .try_get_one::<String>("windows_drive")
+        .ok()
+        .flatten()
+        .map(|drive| {
+            parse_drive_letter(drive).ok_or_else(|| PlaylisterError::WindowsDrive(drive.clone()))
+        })
+        .transpose()?;
+
     if verbose > 2 {
         println!("Path: {:?}", path);
         println!("Output Path: {:?}", opath);
         println!("Music Path: {:?}", mpath);
+        println!("Rewrite Rules: {:?}", rewrite_rules);
+        println!("Extension Rules: {:?}", extension_rules);
+        println!("Only Swap Existing Extensions? {:?}", extension_if_exists);
         println!("Verbosity: {:?}", verbose);
         println!("Use File URL? {:?}", use_file_url);
+        println!("Relative Paths? {:?}", relative);
+        println!("Relative Base: {:?}", relative_base);
+        println!("Format: {:?}", format);
+        println!("Windows Drive: {:?}", windows_drive);
+        println!("Folders: {:?}", folders);
+        println!("Filesystem: {:?}", filesystem);
+        println!("Duplicate Names: {:?}", duplicates);
+        println!("Normalization: {:?}", normalization);
+        println!("Include Kinds: {:?}", include_kinds);
+        println!("Check Smart Playlists? {:?}", check_smart);
+        println!("Smart Playlist Rules: {:?}", smart_rules);
+        println!("Verify Tracks? {:?}", verify);
+        println!("Missing Tracks: {:?}", missing);
+        println!("Relocate From: {:?}", relocate);
+        println!("Relocation Map: {:?}", relocation_map);
+        println!("Profile: {:?}", args.get_one::<String>("profile"));
     }
 
-    PlaylisterArgs {
+    Ok(PlaylisterArgs {
         path,
-        output_path: opath,
-        music_path: mpath,
-        verbose,
-        use_file_url,
-        include_default_playlists,
+        format,
+        job: ExportJob {
+            output_path: opath,
+            relative_base,
+            include_default_playlists,
+            include_kinds,
+            folders,
+            filesystem,
+            duplicates,
+            check_smart,
+            smart_rules,
+            relocate,
+            relocation_map,
+            options: ExportOptions {
+                music_path: mpath,
+                verbose,
+                use_file_url,
+                relative,
+                windows_drive,
+                normalization,
+                verify,
+                missing,
+                rewrite_rules,
+                extension_rules,
+                extension_if_exists,
+                log: Log::default(),
+            },
+        },
+    })
+}
+
+/// Parses the arguments, --format can be any of the registered writers.
+/// With --profile the profile's options are parsed along with them.
+pub fn parse_args(registry: &WriterRegistry) -> Result<PlaylisterArgs, PlaylisterError> {
+    let matcher = arg_parser_factory(registry.names());
+    let argv = env::args_os().collect::<Vec<OsString>>();
+    let args = get_matches(matcher.clone(), &argv);
+    let name = match args.get_one::<String>("profile") {
+        Some(name) => name,
+        None => return parse_and_validate(args),
+    };
+
+    let config = args
+        .get_one::<String>("config")
+        .map(PathBuf::from)
+        .or_else(default_config_path)
+        .ok_or_else(|| {
+            PlaylisterError::Config(String::from("No config file found, give one with --config."))
+        })?;
+
+    let profile = read_profile(&config, name)?;
+    let extra = profile_args(&matcher, &args, &config, &profile)?;
+    parse_and_validate(get_matches(matcher, argv.into_iter().chain(extra)))
+}
+
+/// --help and --version exit successfully, anything else clap turns away
+/// is a usage error.
+fn usage_exit_code(err: &clap::Error) -> i32 {
+    if err.use_stderr() {
+        USAGE_EXIT_CODE
+    } else {
+        0
     }
 }
 
-pub fn parse_args() -> PlaylisterArgs {
-    parse_and_validate(arg_parser_factory())
+fn get_matches<I, T>(matcher: Command, argv: I) -> ArgMatches
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    matcher.try_get_matches_from(argv).unwrap_or_else(|err| {
+        let _ = err.print();
+        process::exit(usage_exit_code(&err));
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_exit_code() {
+        let registry = WriterRegistry::default();
+        let usage = arg_parser_factory(registry.names())
+            .try_get_matches_from(["playlistrs", "Library.xml", "-f", "bogus"])
+            .unwrap_err();
+
+        assert_eq!(usage_exit_code(&usage), 2);
+        assert_ne!(PlaylisterError::NoLibraryRoots.exit_code(), usage_exit_code(&usage));
+
+        let help = arg_parser_factory(registry.names())
+            .try_get_matches_from(["playlistrs", "--help"])
+            .unwrap_err();
+        assert_eq!(usage_exit_code(&help), 0);
+    }
 }
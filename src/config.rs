@@ -0,0 +1,249 @@
+//! config
+//!
+//! Named profiles of command-line options read from a TOML config file so
+//! each device's long list of options only has to be written once:
+//!
+//! ```toml
+//! [profiles.car]
+//! output-path = "/Volumes/CAR/Playlists"
+//! music-path = "/Volumes/CAR/Music"
+//! format = "m3u"
+//! filesystem = "fat32"
+//! rewrite = ["/Users/me/Podcasts => /Volumes/CAR/Podcasts"]
+//! use-file-url = false
+//! ```
+//!
+//! Keys are the long option names. Flags are booleans, options that can be
+//! given more than once are arrays and verbose is a count. Options given on
+//! the command line replace the profile's, along with any of the profile's
+//! that conflict with them.
+//!
+//! The options are added in the order they're written, so rewrite rules
+//! are tried in the order of their arrays and whichever of `rewrite` and
+//! `rewrite-regex` comes first in the profile is tried first.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+use playlistrs::PlaylisterError;
+
+static CONFIG_FILE: &str = "playlistrs/config.toml";
+
+/// Options that choose the profile rather than being part of one.
+static RESERVED: [&str; 2] = ["profile", "config"];
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    profiles: HashMap<String, Table>,
+}
+
+/// $XDG_CONFIG_HOME/playlistrs/config.toml, falling back to ~/.config on
+/// Unix and %APPDATA% on Windows.
+pub fn default_config_path() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .map(|dir| dir.join(CONFIG_FILE))
+}
+
+fn parse_profile(contents: &str, name: &str) -> Result<Table, String> {
+    let mut config: Config = toml::from_str(contents).map_err(|err| err.to_string())?;
+    config
+        .profiles
+        .remove(name)
+        .ok_or_else(|| format!("No profile named {name}."))
+}
+
+pub fn read_profile(path: &Path, name: &str) -> Result<Table, PlaylisterError> {
+    fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|contents| parse_profile(&contents, name))
+        .map_err(|err| {
+            PlaylisterError::Config(format!("Invalid config file {}: {err}", path.display()))
+        })
+}
+
+fn conflicts(cmd: &Command, arg: &Arg, other: &Arg) -> bool {
+    let conflicts_with = |a: &Arg, b: &Arg| {
+        cmd.get_arg_conflicts_with(a)
+            .iter()
+            .any(|conflict| conflict.get_id() == b.get_id())
+    };
+
+    arg.get_id() == other.get_id() || conflicts_with(arg, other) || conflicts_with(other, arg)
+}
+
+/// The arguments a profile adds to the command line. They go on the end so
+/// clap validates them the same as if they had been typed, once their
+/// values are checked to be the right type for their options.
+pub fn profile_args(
+    cmd: &Command,
+    matches: &ArgMatches,
+    config: &Path,
+    profile: &Table,
+) -> Result<Vec<OsString>, PlaylisterError> {
+    let error = |message: String| {
+        PlaylisterError::Config(format!("Invalid config file {}: {message}", config.display()))
+    };
+
+    let given = cmd
+        .get_arguments()
+        .filter(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine))
+        .collect::<Vec<&Arg>>();
+
+    let mut args = Vec::new();
+    for (key, value) in profile {
+        let arg = cmd
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key.as_str()) && !RESERVED.contains(&key.as_str()))
+            .ok_or_else(|| error(format!("unknown profile option {key}.")))?;
+
+        if given.iter().any(|other| conflicts(cmd, arg, other)) {
+            continue;
+        }
+
+        let flag = format!("--{key}");
+        let invalid = |expected: &str| {
+            error(format!("profile option {key} should be {expected}, not {value}."))
+        };
+        match (arg.get_action(), value) {
+            (ArgAction::Count, Value::Integer(count)) if *count >= 0 => {
+                args.extend((0..*count).map(|_| OsString::from(&flag)));
+            }
+            (ArgAction::Count, _) => return Err(invalid("a count")),
+            (ArgAction::SetTrue | ArgAction::SetFalse, Value::Boolean(set)) => {
+                if *set {
+                    args.push(OsString::from(&flag));
+                }
+            }
+            (ArgAction::SetTrue | ArgAction::SetFalse, _) => return Err(invalid("true or false")),
+            (ArgAction::Set | ArgAction::Append, Value::String(value)) => {
+                args.push(OsString::from(format!("{flag}={value}")));
+            }
+            (ArgAction::Set, _) => return Err(invalid("a string")),
+            (ArgAction::Append, Value::Array(values)) => {
+                for value in values {
+                    let value = value.as_str().ok_or_else(|| invalid("an array of strings"))?;
+                    args.push(OsString::from(format!("{flag}={value}")));
+                }
+            }
+            (ArgAction::Append, _) => return Err(invalid("a string or an array of strings")),
+            _ => return Err(error(format!("{key} can't be set in a profile."))),
+        }
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command() -> Command {
+        Command::new("test")
+            .arg(Arg::new("format").long("format").default_value("m3u"))
+            .arg(Arg::new("rewrite").long("rewrite").action(ArgAction::Append))
+            .arg(Arg::new("rewrite_regex").long("rewrite-regex").action(ArgAction::Append))
+            .arg(Arg::new("use_file_url").long("use-file-url").action(ArgAction::SetTrue))
+            .arg(
+                Arg::new("relative")
+                    .long("relative")
+                    .conflicts_with("use_file_url")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(Arg::new("verbose").long("verbose").action(ArgAction::Count))
+            .arg(Arg::new("profile").long("profile"))
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        let contents = "[profiles.car]\nformat = \"m3u8\"\n\n[profiles.phone]\nrelative = true\n";
+        let profile = parse_profile(contents, "car").unwrap();
+        assert_eq!(profile["format"].as_str(), Some("m3u8"));
+        assert_eq!(parse_profile(contents, "boat").unwrap_err(), "No profile named boat.");
+        assert!(parse_profile("[car]\nformat = \"m3u8\"\n", "car").is_err());
+    }
+
+    #[test]
+    fn test_profile_args() {
+        let cmd = command();
+        let profile = parse_profile(
+            "[profiles.car]\nformat = \"m3u8\"\nrewrite = [\"/a => /b\", \"/c => /d\"]\n\
+            use-file-url = true\nverbose = 2\n",
+            "car",
+        )
+        .unwrap();
+
+        let matches = cmd.clone().get_matches_from(["test"]);
+        let args = profile_args(&cmd, &matches, Path::new("config.toml"), &profile).unwrap();
+        let matches = cmd.clone().get_matches_from([OsString::from("test")].into_iter().chain(args));
+        assert_eq!(matches.get_one::<String>("format").unwrap(), "m3u8");
+        assert_eq!(matches.get_many::<String>("rewrite").unwrap().count(), 2);
+        assert!(matches.get_flag("use_file_url"));
+        assert_eq!(matches.get_count("verbose"), 2);
+
+        // The command line wins, including over options it conflicts with.
+        let matches = cmd.clone().get_matches_from(["test", "--format", "xspf", "--relative"]);
+        let args = profile_args(&cmd, &matches, Path::new("config.toml"), &profile).unwrap();
+        assert_eq!(args, ["--rewrite=/a => /b", "--rewrite=/c => /d", "--verbose", "--verbose"]);
+    }
+
+    #[test]
+    fn test_profile_args_order() {
+        let cmd = command();
+        let profile = parse_profile(
+            "[profiles.car]
+rewrite-regex = [\"^/z => /y\"]
+rewrite = [\"/c => /d\", \"/a => /b\"]
+",
+            "car",
+        )
+        .unwrap();
+
+        let matches = cmd.clone().get_matches_from(["test"]);
+        let args = profile_args(&cmd, &matches, Path::new("config.toml"), &profile).unwrap();
+        assert_eq!(args, ["--rewrite-regex=^/z => /y", "--rewrite=/c => /d", "--rewrite=/a => /b"]);
+    }
+
+    #[test]
+    fn test_profile_args_invalid() {
+        let cmd = command();
+        let matches = cmd.clone().get_matches_from(["test"]);
+        let cases = [
+            "profile = \"car\"",
+            "size = 1",
+            "verbose = -1",
+            "rewrite = [1]",
+            "use-file-url = \"yes\"",
+            "format = true",
+            "format = 1",
+        ];
+        for contents in cases {
+            let profile = parse_profile(&format!("[profiles.car]\n{contents}\n"), "car").unwrap();
+            let err = profile_args(&cmd, &matches, Path::new("config.toml"), &profile).unwrap_err();
+            assert_eq!(err.exit_code(), 14);
+            assert!(err.to_string().contains("config.toml"));
+        }
+
+        let profile = parse_profile("[profiles.car]\nuse-file-url = \"yes\"\n", "car").unwrap();
+        let err = profile_args(&cmd, &matches, Path::new("config.toml"), &profile).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid config file config.toml: profile option use-file-url should be true or false, not \"yes\"."
+        );
+    }
+}
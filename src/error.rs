@@ -0,0 +1,158 @@
+//! error
+//!
+//! Everything that can go wrong in a run and the status the command line
+//! exits with for it:
+//!
+//! | Status | Error                                                      |
+//! |--------|------------------------------------------------------------|
+//! | 1      | The library couldn't be read                               |
+//! | 2      | The arguments are invalid, from the argument parser        |
+//! | 3      | The library has no tracks to work out a --music-path from  |
+//! | 4      | The library path isn't a file                              |
+//! | 5      | The library path has no parent directory                   |
+//! | 6      | The output path isn't a directory                          |
+//! | 7      | --windows-drive isn't a drive letter                       |
+//! | 8      | A --rewrite or --ext-map rule is invalid                   |
+//! | 9      | --relative-base isn't absolute                             |
+//! | 10     | --verify found missing tracks                              |
+//! | 11     | --relocate couldn't index or write its map                 |
+//! | 12     | --duplicates error found playlists with the same name      |
+//! | 13     | A playlist or its directory couldn't be written            |
+//! | 14     | The config file or --profile is invalid                    |
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::relocate::RelocateError;
+use crate::rewrite::RewriteError;
+use crate::source::SourceError;
+
+#[derive(Debug)]
+pub enum PlaylisterError {
+    Library(SourceError),
+    NoLibraryRoots,
+    InputFile(PathBuf),
+    InputDir(PathBuf),
+    OutputDir(PathBuf),
+    WindowsDrive(String),
+    Rule(RewriteError),
+    RelativeBase(PathBuf),
+    MissingTracks { missing: usize, playlists: usize },
+    Relocate(RelocateError),
+    /// The full names of each group of playlists sharing a file name.
+    DuplicateNames(Vec<Vec<String>>),
+    CreateDir { path: PathBuf, source: io::Error },
+    Write { path: PathBuf, source: io::Error },
+    Config(String),
+}
+
+impl PlaylisterError {
+    /// Never 2, that's left to the argument parser's usage errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PlaylisterError::Library(_) => 1,
+            PlaylisterError::NoLibraryRoots => 3,
+            PlaylisterError::InputFile(_) => 4,
+            PlaylisterError::InputDir(_) => 5,
+            PlaylisterError::OutputDir(_) => 6,
+            PlaylisterError::WindowsDrive(_) => 7,
+            PlaylisterError::Rule(_) => 8,
+            PlaylisterError::RelativeBase(_) => 9,
+            PlaylisterError::MissingTracks { .. } => 10,
+            PlaylisterError::Relocate(_) => 11,
+            PlaylisterError::DuplicateNames(_) => 12,
+            PlaylisterError::CreateDir { .. } | PlaylisterError::Write { .. } => 13,
+            PlaylisterError::Config(_) => 14,
+        }
+    }
+}
+
+impl Error for PlaylisterError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PlaylisterError::Library(err) => Some(err),
+            PlaylisterError::Rule(err) => Some(err),
+            PlaylisterError::Relocate(err) => Some(err),
+            PlaylisterError::CreateDir { source, .. } | PlaylisterError::Write { source, .. } => {
+                Some(source)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PlaylisterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlaylisterError::Library(err) => write!(f, "{err}"),
+            PlaylisterError::NoLibraryRoots => write!(
+                f,
+                "Cannot find the library's music folder, no tracks have a location."
+            ),
+            PlaylisterError::InputFile(_) => write!(
+                f,
+                "The supplied path must point to a valid iTunes® xml library file"
+            ),
+            PlaylisterError::InputDir(_) => write!(f, "Input directory invalid."),
+            PlaylisterError::OutputDir(_) => write!(f, "Output path must be a valid directory."),
+            PlaylisterError::WindowsDrive(_) => {
+                write!(f, "Windows drive must be a single letter like D or D:")
+            }
+            PlaylisterError::Rule(err) => write!(f, "{err}"),
+            PlaylisterError::RelativeBase(_) => write!(f, "Relative base must be an absolute path."),
+            PlaylisterError::MissingTracks { missing, playlists } => write!(
+                f,
+                "{missing} track(s) missing from {playlists} playlist(s)."
+            ),
+            PlaylisterError::Relocate(err) => write!(f, "{err}"),
+            PlaylisterError::DuplicateNames(groups) => {
+                let lines = groups
+                    .iter()
+                    .map(|names| {
+                        format!("Playlists {} would be written to the same file.", names.join(", "))
+                    })
+                    .collect::<Vec<String>>();
+                write!(f, "{}", lines.join("\n"))
+            }
+            PlaylisterError::CreateDir { path, source } => {
+                write!(f, "Couldn't create directory {}: {source}", path.display())
+            }
+            PlaylisterError::Write { path, source } => {
+                write!(f, "Couldn't write file {}: {source}", path.display())
+            }
+            PlaylisterError::Config(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_and_exit_code() {
+        let err = PlaylisterError::Write {
+            path: PathBuf::from("/out/Mix.m3u"),
+            source: io::Error::new(io::ErrorKind::PermissionDenied, "denied"),
+        };
+        assert_eq!(err.to_string(), "Couldn't write file /out/Mix.m3u: denied");
+        assert_eq!(err.exit_code(), 13);
+        assert!(err.source().is_some());
+
+        let err = PlaylisterError::DuplicateNames(vec![
+            vec![String::from("Rock/Mix"), String::from("Mix")],
+            vec![String::from("A"), String::from("a")],
+        ]);
+        assert_eq!(
+            err.to_string(),
+            "Playlists Rock/Mix, Mix would be written to the same file.\n\
+            Playlists A, a would be written to the same file."
+        );
+        assert_eq!(err.exit_code(), 12);
+    }
+}
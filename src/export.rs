@@ -0,0 +1,532 @@
+//! export
+//!
+//! The options that control how tracks are located and written, and the
+//! export of a whole library the way the command line runs it.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::canonicalize;
+use std::path::PathBuf;
+use std::ptr;
+use std::time::SystemTime;
+
+use crate::error::PlaylisterError;
+use crate::library::{get_library_roots, tracks_outside_music_folder, Library, Playlist, PlaylistKind};
+use crate::normalize::Normalization;
+use crate::path_utils::{create_output_dir, write_to_file, LocationContext};
+use crate::playlist_tree::{find_duplicates, PlaylistTree};
+use crate::relocate::{read_relocation_map, RelocationReport, Relocator};
+use crate::rewrite::{rewrite_hit_counts, ExtensionRule, RewriteHits, RewriteRule};
+use crate::sanitize::{sanitize_file_name, FileNamer, FsProfile};
+use crate::smart_eval::{check_smart_playlist, SmartContext};
+use crate::smart_export::{translate_smart_playlist, SmartFormat};
+use crate::writer::{render_playlist, PlaylistWriter};
+
+/// What to do with tracks that don't exist at their resolved location
+/// when verifying.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissingMode {
+    #[default]
+    Keep,
+    Drop,
+    Comment,
+}
+
+impl MissingMode {
+    pub fn from_arg(arg: &str) -> Option<MissingMode> {
+        match arg {
+            "keep" => Some(MissingMode::Keep),
+            "drop" => Some(MissingMode::Drop),
+            "comment" => Some(MissingMode::Comment),
+            _ => None,
+        }
+    }
+}
+
+/// How much a logged message matters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogLevel {
+    /// What the export is doing, only logged when verbose.
+    Info,
+    /// Something the user should look at, the export carries on.
+    Warning,
+}
+
+/// Where the progress and warnings of an export go. The default drops
+/// them, the command line prints them.
+#[derive(Default)]
+pub struct Log(Option<Box<LogFn>>);
+
+type LogFn = dyn Fn(LogLevel, &str);
+
+impl Log {
+    pub fn new<F>(log: F) -> Log
+    where
+        F: Fn(LogLevel, &str) + 'static,
+    {
+        Log(Some(Box::new(log)))
+    }
+
+    pub fn info(&self, message: &str) {
+        if let Some(log) = &self.0 {
+            log(LogLevel::Info, message);
+        }
+    }
+
+    pub fn warn(&self, message: &str) {
+        if let Some(log) = &self.0 {
+            log(LogLevel::Warning, message);
+        }
+    }
+}
+
+impl fmt::Debug for Log {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let log = if self.0.is_some() { "Log(..)" } else { "Log(None)" };
+        write!(f, "{log}")
+    }
+}
+
+/// How track locations are resolved and written. The defaults write the
+/// locations from the library as plain, NFC normalized paths.
+#[derive(Debug, Default)]
+pub struct ExportOptions {
+    /// Replaces the library root each track is in.
+    pub music_path: Option<PathBuf>,
+    /// How much progress and detail to log.
+    pub verbose: u8,
+    pub log: Log,
+    pub use_file_url: bool,
+    /// Write locations relative to the playlist's directory.
+    pub relative: bool,
+    /// Write Windows paths rooted at this drive.
+    pub windows_drive: Option<char>,
+    pub normalization: Normalization,
+    /// Check that every track exists, see `missing`.
+    pub verify: bool,
+    pub missing: MissingMode,
+    /// Tried in order before `music_path`.
+    pub rewrite_rules: Vec<RewriteRule>,
+    pub extension_rules: Vec<ExtensionRule>,
+    /// Only swap extensions when the swapped file exists.
+    pub extension_if_exists: bool,
+}
+
+/// What to do with playlist folders.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FolderMode {
+    /// Write playlists into matching subdirectories.
+    #[default]
+    Mirror,
+    /// Write the folders as playlists of everything inside them.
+    Flatten,
+    Skip,
+}
+
+impl FolderMode {
+    pub fn from_arg(arg: &str) -> Option<FolderMode> {
+        match arg {
+            "mirror" => Some(FolderMode::Mirror),
+            "flatten" => Some(FolderMode::Flatten),
+            "skip" => Some(FolderMode::Skip),
+            _ => None,
+        }
+    }
+}
+
+/// What to do with playlists that would be written to the same file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateMode {
+    /// Number them.
+    #[default]
+    Suffix,
+    /// Add the names of their folders.
+    Folder,
+    /// Fail before writing anything.
+    Error,
+}
+
+impl DuplicateMode {
+    pub fn from_arg(arg: &str) -> Option<DuplicateMode> {
+        match arg {
+            "suffix" => Some(DuplicateMode::Suffix),
+            "folder" => Some(DuplicateMode::Folder),
+            "error" => Some(DuplicateMode::Error),
+            _ => None,
+        }
+    }
+}
+
+/// Which playlists to write and where, along with the options for their
+/// tracks.
+#[derive(Debug, Default)]
+pub struct ExportJob {
+    pub output_path: PathBuf,
+    /// What relative entries are computed against, the output path if
+    /// not given.
+    pub relative_base: Option<PathBuf>,
+    /// Write the built-in playlists as well as the user's.
+    pub include_default_playlists: bool,
+    /// Built-in playlists to write along with the user's.
+    pub include_kinds: Vec<PlaylistKind>,
+    pub folders: FolderMode,
+    pub filesystem: FsProfile,
+    pub duplicates: DuplicateMode,
+    /// Compare smart playlists against their rules.
+    pub check_smart: bool,
+    /// Write smart playlists as rules in this format where possible.
+    pub smart_rules: Option<SmartFormat>,
+    /// Look for missing tracks in this directory.
+    pub relocate: Option<PathBuf>,
+    /// Where relocations are read from and saved to.
+    pub relocation_map: Option<PathBuf>,
+    pub options: ExportOptions,
+}
+
+/// What an export did, for the end of the run.
+#[derive(Debug, Default)]
+pub struct ExportSummary {
+    /// How many playlists were selected to be written.
+    pub playlists: usize,
+    pub written: usize,
+    /// The full names of playlists renamed for sharing a name and where
+    /// they were written, relative to the output path.
+    pub renamed: Vec<(String, PathBuf)>,
+    /// Tracks left out for being outside the library roots or having no
+    /// relative path from the playlist.
+    pub unresolved: usize,
+    /// How many tracks --verify looked for.
+    pub checked: usize,
+    /// The tracks --verify couldn't find, by playlist.
+    pub missing: Vec<(String, Vec<PathBuf>)>,
+    /// Each rewrite rule with how many tracks it matched.
+    pub rewrite_hits: Vec<(String, usize)>,
+    /// What --relocate found, if it was given.
+    pub relocation: Option<RelocationReport>,
+    /// What couldn't be written, the rest of the export went ahead.
+    pub failures: Vec<PlaylisterError>,
+}
+
+fn should_export(job: &ExportJob, playlist: &Playlist) -> bool {
+    if playlist.folder && job.folders != FolderMode::Flatten {
+        if job.options.verbose > 1 {
+            job.options.log.info(&format!("Skipping folder {}.", playlist.name));
+        }
+        return false;
+    }
+
+    let kind = playlist.kind;
+    let include = kind == PlaylistKind::User
+        || job.include_default_playlists
+        || job.include_kinds.contains(&kind);
+
+    if !include && job.options.verbose > 1 {
+        job.options.log.info(&format!("Skipping built-in playlist {} ({:?}).", playlist.name, kind));
+    }
+
+    include
+}
+
+/// Writes the library's playlists with the writer. Problems with the job
+/// itself are returned before anything is written, playlists that can't be
+/// written are collected in the summary so the rest still are.
+pub fn export_library(
+    writer: &dyn PlaylistWriter,
+    library: &Library,
+    job: &ExportJob,
+) -> Result<ExportSummary, PlaylisterError> {
+    let options = &job.options;
+    let roots = get_library_roots(library);
+    if roots.is_empty() && options.music_path.is_some() {
+        return Err(PlaylisterError::NoLibraryRoots);
+    }
+
+    if options.verbose > 2 {
+        options.log.info(&format!("Library Roots: {:?}", roots));
+    }
+
+    if options.verbose > 1 {
+        for track in tracks_outside_music_folder(library) {
+            options.log.info(&format!("Track {} is outside the music folder.", track.get_name()));
+        }
+    }
+
+    let tree = PlaylistTree::new(&library.playlists);
+    let smart_context = job
+        .check_smart
+        .then(|| SmartContext::new(&library.tracks, &library.playlists, SystemTime::now()));
+
+    // Relative entries are computed against where the playlists end up, so
+    // the output path needs to be absolute.
+    let relative_base = job
+        .relative_base
+        .clone()
+        .or_else(|| canonicalize(&job.output_path).ok())
+        .unwrap_or_else(|| job.output_path.clone());
+
+    let relocator = match &job.relocate {
+        Some(dir) => {
+            let map = match &job.relocation_map {
+                Some(map) => read_relocation_map(map),
+                None => Ok(HashMap::new()),
+            };
+
+            let relocator = map
+                .and_then(|map| Relocator::new(options, dir, map))
+                .map_err(PlaylisterError::Relocate)?;
+            if options.verbose > 0 {
+                options.log.info(&format!("Indexed {} file(s) to relocate from.", relocator.indexed()));
+            }
+            Some(relocator)
+        }
+        None => None,
+    };
+
+    let exports = library
+        .playlists
+        .iter()
+        .filter(|playlist| should_export(job, playlist))
+        .map(|playlist| {
+            let folder = match job.folders {
+                FolderMode::Mirror => {
+                    tree.folder_path(playlist, options.normalization, job.filesystem)
+                }
+                FolderMode::Flatten | FolderMode::Skip => PathBuf::new(),
+            };
+            (playlist, folder)
+        })
+        .collect::<Vec<_>>();
+
+    // Bail before writing anything so a failed run doesn't leave half the
+    // playlists behind.
+    let duplicates = find_duplicates(options.normalization, job.filesystem, &exports);
+    if job.duplicates == DuplicateMode::Error && !duplicates.is_empty() {
+        let names = duplicates
+            .iter()
+            .map(|group| group.iter().map(|playlist| tree.full_name(playlist)).collect())
+            .collect();
+        return Err(PlaylisterError::DuplicateNames(names));
+    }
+
+    let rewrite_hits = RewriteHits::default();
+    let mut namer = FileNamer::new(job.filesystem);
+    let mut summary = ExportSummary {
+        playlists: exports.len(),
+        ..Default::default()
+    };
+    for (playlist, folder) in &exports {
+        if options.verbose > 0 {
+            options.log.info(&format!("Converting playlist {}.", playlist.name));
+        }
+
+        if let Some(smart_context) = &smart_context {
+            check_smart_playlist(options, smart_context, playlist);
+        }
+
+        let output_dir = job.output_path.join(folder);
+        if let Err(err) = create_output_dir(options, &output_dir) {
+            summary.failures.push(err);
+            continue;
+        }
+
+        let playlist_dir = relative_base.join(folder);
+        let ctx = LocationContext {
+            relocator: relocator.as_ref(),
+            rewrite_hits: Some(&rewrite_hits),
+            ..LocationContext::new(&roots, &playlist_dir)
+        };
+        let smart_rules = job.smart_rules.and_then(|format| {
+            translate_smart_playlist(options, format, playlist, &library.playlists)
+        });
+
+        let (name, contents) = match smart_rules {
+            Some((name, contents)) => (name, contents.into_bytes()),
+            None => render_playlist(writer, &ctx, options, &library.tracks, playlist),
+        };
+
+        // Playlists that share a name get their folders added to it first,
+        // the namer numbers any that still collide.
+        let duplicate = duplicates.iter().flatten().any(|other| ptr::eq(*other, *playlist));
+        let qualified = match job.duplicates {
+            DuplicateMode::Folder if duplicate => {
+                let mut names = tree.folder_names(playlist);
+                names.push(&name);
+                Some(names.join(" - "))
+            }
+            DuplicateMode::Suffix | DuplicateMode::Folder | DuplicateMode::Error => None,
+        };
+
+        let normalized = options.normalization.apply(&name);
+        let file_name = match &qualified {
+            Some(qualified) => namer.claim(&output_dir, &options.normalization.apply(qualified)),
+            None => namer.claim(&output_dir, &normalized),
+        };
+
+        if duplicate && file_name != sanitize_file_name(job.filesystem, &normalized) {
+            summary.renamed.push((tree.full_name(playlist), folder.join(&file_name)));
+        } else if file_name != name && options.verbose > 0 {
+            options.log.info(&format!("Writing playlist {} as {file_name}.", playlist.name));
+        }
+
+        let path = output_dir.join(file_name);
+        match write_to_file(options, &path, &contents) {
+            Ok(()) => {
+                summary.written += 1;
+                if options.verbose > 1 {
+                    options.log.info("Done.");
+                }
+            }
+            Err(err) => summary.failures.push(err),
+        }
+
+        summary.unresolved += ctx.unresolved.get();
+        summary.checked += ctx.checked.get();
+        let missing = ctx.missing.take();
+        if !missing.is_empty() {
+            summary.missing.push((playlist.name.clone(), missing));
+        }
+    }
+
+    summary.rewrite_hits = rewrite_hit_counts(&options.rewrite_rules, &rewrite_hits);
+    if let Some(relocator) = &relocator {
+        summary.relocation = Some(relocator.report());
+        if let Some(map) = &job.relocation_map {
+            if let Err(err) = relocator.write_map(map) {
+                summary.failures.push(PlaylisterError::Relocate(err));
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, read_dir, read_to_string, remove_dir_all};
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::library::PlaylistItem;
+    use crate::m3u::M3uWriter;
+    use crate::track::Track;
+
+    fn library() -> Library {
+        let track = Track {
+            location: Some(PathBuf::from("/Music/a.mp3")),
+            ..Default::default()
+        };
+        let mix = |id: &str| Playlist {
+            name: String::from("Mix"),
+            persistent_id: Some(String::from(id)),
+            playlist_items: vec![PlaylistItem { track_id: String::from("1") }],
+            ..Default::default()
+        };
+        let podcasts = Playlist {
+            name: String::from("Podcasts"),
+            kind: PlaylistKind::Podcasts,
+            ..Default::default()
+        };
+
+        Library {
+            music_folder: None,
+            tracks: HashMap::from([(String::from("1"), track)]),
+            playlists: vec![mix("A"), mix("B"), podcasts],
+        }
+    }
+
+    fn job(name: &str) -> ExportJob {
+        let output_path = temp_dir().join(format!("playlistrs-export-{name}-{}", std::process::id()));
+        let _ = remove_dir_all(&output_path);
+        create_dir_all(&output_path).unwrap();
+        ExportJob {
+            output_path,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_export_library() {
+        let job = job("suffix");
+        let summary = export_library(&M3uWriter, &library(), &job).unwrap();
+        let first = read_to_string(job.output_path.join("Mix.m3u"));
+        let second = read_to_string(job.output_path.join("Mix (2).m3u"));
+        remove_dir_all(&job.output_path).unwrap();
+
+        // The built-in podcasts playlist is left out.
+        assert_eq!((summary.playlists, summary.written), (2, 2));
+        assert_eq!(summary.renamed, [(String::from("Mix"), PathBuf::from("Mix (2).m3u"))]);
+        assert!(summary.failures.is_empty());
+        assert!(first.unwrap().contains("/Music/a.mp3"));
+        assert!(second.unwrap().contains("/Music/a.mp3"));
+    }
+
+    #[test]
+    fn test_export_library_log_and_missing() {
+        let logged = Rc::new(RefCell::new(Vec::new()));
+        let log = Rc::clone(&logged);
+        let job = ExportJob {
+            options: ExportOptions {
+                verbose: 1,
+                verify: true,
+                log: Log::new(move |level, message| log.borrow_mut().push((level, message.to_string()))),
+                ..Default::default()
+            },
+            ..job("log")
+        };
+
+        let summary = export_library(&M3uWriter, &library(), &job).unwrap();
+        remove_dir_all(&job.output_path).unwrap();
+
+        let missing = vec![PathBuf::from("/Music/a.mp3")];
+        assert_eq!(summary.checked, 2);
+        assert_eq!(
+            summary.missing,
+            [(String::from("Mix"), missing.clone()), (String::from("Mix"), missing)]
+        );
+        assert_eq!(
+            logged.borrow().first(),
+            Some(&(LogLevel::Info, String::from("Converting playlist Mix.")))
+        );
+    }
+
+    #[test]
+    fn test_export_library_relative_other_drive() {
+        let mut library = library();
+        library.tracks.get_mut("1").unwrap().location = Some(PathBuf::from("C:/Music/a.mp3"));
+        library.playlists.truncate(1);
+        let job = ExportJob {
+            relative_base: Some(PathBuf::from("D:/Playlists")),
+            options: ExportOptions {
+                relative: true,
+                ..Default::default()
+            },
+            ..job("relative")
+        };
+
+        let summary = export_library(&M3uWriter, &library, &job).unwrap();
+        let contents = read_to_string(job.output_path.join("Mix.m3u")).unwrap();
+        remove_dir_all(&job.output_path).unwrap();
+
+        assert_eq!((summary.written, summary.unresolved), (1, 1));
+        assert!(!contents.contains("a.mp3"));
+    }
+
+    #[test]
+    fn test_export_library_duplicates_error() {
+        let job = ExportJob {
+            duplicates: DuplicateMode::Error,
+            ..job("error")
+        };
+        let result = export_library(&M3uWriter, &library(), &job);
+        let written = read_dir(&job.output_path).unwrap().count();
+        remove_dir_all(&job.output_path).unwrap();
+
+        assert!(matches!(result, Err(PlaylisterError::DuplicateNames(_))));
+        assert_eq!(written, 0);
+    }
+}
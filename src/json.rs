@@ -0,0 +1,402 @@
+//! json
+//!
+//! Contains functions pertaining to the creation of JSON playlists, both
+//! JSPF (the JSON flavor of XSPF) and a native dump of the library model
+//! for post-processing with other tools.
+//!
+//! The native format is versioned with `schema_version` and any change to
+//! it that isn't purely additive will bump the version. Version 2 is:
+//!
+//! ```text
+//! {
+//!   "schema_version": 2,
+//!   "name": String,
+//!   "playlist_items": [{ "track_id": String }],
+//!   "persistent_id": String | null,
+//!   "parent_persistent_id": String | null,
+//!   "folder": Boolean,
+//!   "tracks": [{
+//!     "track_id": String,
+//!     "resolved_location": String | null,
+//!     ...every Track field by its snake_case name, null when absent
+//!   }]
+//! }
+//! ```
+//!
+//! `resolved_location` is the location as it would be written to an m3u,
+//! i.e. after applying --music-path, --use-file-url etc. whereas
+//! `location` is the path from the library. Tracks that are missing from
+//! the library are left out of `tracks` but still appear in
+//! `playlist_items`. Every string is written with --normalize applied, the
+//! same as the other formats.
+//!
+//! Version 1 wrote track IDs as numbers along with the iTunes® playlist
+//! flags.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::export::ExportOptions;
+use crate::library::Playlist;
+use crate::normalize::Normalization;
+use crate::path_utils::{
+    format_location, resolve_path, to_uri, verify_path, LocationContext,
+};
+use crate::track::Track;
+use crate::writer::PlaylistWriter;
+
+static SCHEMA_VERSION: u32 = 2;
+
+/// A track as it appears in the native format, less its ID.
+#[derive(Serialize)]
+struct NativeEntry<'a> {
+    resolved_location: Option<String>,
+
+    #[serde(flatten)]
+    track: &'a Track,
+}
+
+#[derive(Serialize)]
+struct NativeItem<'a> {
+    track_id: &'a str,
+}
+
+#[derive(Serialize)]
+struct NativeTrack<'a> {
+    track_id: &'a str,
+
+    #[serde(flatten)]
+    entry: NativeEntry<'a>,
+}
+
+#[derive(Serialize)]
+struct NativePlaylist<'a> {
+    schema_version: u32,
+    name: &'a str,
+    playlist_items: Vec<NativeItem<'a>>,
+    persistent_id: Option<&'a str>,
+    parent_persistent_id: Option<&'a str>,
+    folder: bool,
+    tracks: Vec<NativeTrack<'a>>,
+}
+
+fn to_jspf_track(ctx: &LocationContext, args: &ExportOptions, track: &Track) -> Option<Value> {
+    let name = args.normalization.apply(track.get_name());
+    if args.verbose > 3 {
+        args.log.info(&format!("Converting track {name}"));
+    }
+
+    // Same as xspf, locations are always URIs.
+    let path = resolve_path(ctx, args, track)?;
+    if verify_path(ctx, args, &path)? {
+        return None;
+    }
+
+    let location = to_uri(ctx, args, &path)?;
+
+    let mut entry = Map::new();
+    entry.insert(String::from("location"), json!([location]));
+    if let Some(id) = &track.persistent_id {
+        entry.insert(
+            String::from("identifier"),
+            json!([format!("urn:itunes:persistent-id:{id}")]),
+        );
+    }
+
+    entry.insert(String::from("title"), json!(name));
+    entry.insert(
+        String::from("creator"),
+        json!(args.normalization.apply(track.get_artist())),
+    );
+    if let Some(album) = &track.album {
+        entry.insert(String::from("album"), json!(args.normalization.apply(album)));
+    }
+
+    if let Some(track_number) = track.track_number {
+        entry.insert(String::from("trackNum"), json!(track_number));
+    }
+
+    entry.insert(String::from("duration"), json!(track.duration));
+    Some(Value::Object(entry))
+}
+
+fn to_native_entry<'a>(
+    ctx: &LocationContext,
+    args: &ExportOptions,
+    track: &'a Track,
+) -> Option<NativeEntry<'a>> {
+    if args.verbose > 3 {
+        args.log.info(&format!("Converting track {}", track.get_name()));
+    }
+
+    // JSON has no comments, so missing tracks are dropped either way.
+    let resolved = resolve_path(ctx, args, track);
+    let resolved_location = match &resolved {
+        Some(path) if verify_path(ctx, args, path) != Some(false) => return None,
+        Some(path) => format_location(ctx, args, path).map(|location| location.into_owned()),
+        None => None,
+    };
+
+    Some(NativeEntry {
+        resolved_location,
+        track,
+    })
+}
+
+/// The library model is kept as the library has it, so its text is
+/// normalized on the way out.
+fn normalize_value(normalization: Normalization, value: &mut Value) {
+    match value {
+        Value::String(text) => {
+            if let Cow::Owned(normalized) = normalization.apply(text) {
+                *text = normalized;
+            }
+        }
+        Value::Array(values) => {
+            values.iter_mut().for_each(|value| normalize_value(normalization, value));
+        }
+        Value::Object(map) => {
+            map.values_mut().for_each(|value| normalize_value(normalization, value));
+        }
+        _ => {}
+    }
+}
+
+/// JSPF, the JSON flavor of XSPF.
+pub struct JspfWriter;
+
+impl PlaylistWriter for JspfWriter {
+    fn name(&self) -> &'static str {
+        "jspf"
+    }
+
+    fn extension(&self) -> &str {
+        "jspf"
+    }
+
+    fn render(
+        &self,
+        ctx: &LocationContext,
+        options: &ExportOptions,
+        tracks: &HashMap<String, Track>,
+        playlist: &Playlist,
+    ) -> String {
+        let entries = playlist
+            .playlist_items
+            .iter()
+            .filter_map(|item| to_jspf_track(ctx, options, tracks.get(&item.track_id)?))
+            .collect::<Vec<Value>>();
+
+        let jspf = json!({
+            "playlist": {
+                "title": options.normalization.apply(&playlist.name),
+                "track": entries,
+            }
+        });
+
+        // Built from strings, there's nothing in a Value that can fail.
+        let contents = serde_json::to_string_pretty(&jspf).expect("Playlist is valid JSON");
+        contents + "\n"
+    }
+}
+
+/// The native dump of the playlist and its tracks.
+pub struct JsonWriter;
+
+impl PlaylistWriter for JsonWriter {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn extension(&self) -> &str {
+        "json"
+    }
+
+    fn render(
+        &self,
+        ctx: &LocationContext,
+        options: &ExportOptions,
+        tracks: &HashMap<String, Track>,
+        playlist: &Playlist,
+    ) -> String {
+        let native_tracks = playlist
+            .playlist_items
+            .iter()
+            .filter_map(|item| {
+                let entry = to_native_entry(ctx, options, tracks.get(&item.track_id)?)?;
+                Some(NativeTrack {
+                    track_id: &item.track_id,
+                    entry,
+                })
+            })
+            .collect::<Vec<NativeTrack>>();
+
+        let native = NativePlaylist {
+            schema_version: SCHEMA_VERSION,
+            name: &playlist.name,
+            playlist_items: playlist
+                .playlist_items
+                .iter()
+                .map(|item| NativeItem {
+                    track_id: &item.track_id,
+                })
+                .collect(),
+            persistent_id: playlist.persistent_id.as_deref(),
+            parent_persistent_id: playlist.parent_persistent_id.as_deref(),
+            folder: playlist.folder,
+            tracks: native_tracks,
+        };
+
+        // Paths are written lossily and every map is keyed by strings so
+        // there's nothing in the model that can fail to serialize.
+        let mut native = serde_json::to_value(&native).expect("Playlist is valid JSON");
+        normalize_value(options.normalization, &mut native);
+        let contents = serde_json::to_string_pretty(&native).expect("Playlist is valid JSON");
+        contents + "\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use super::*;
+    use crate::library::PlaylistItem;
+
+    fn fixture() -> (Playlist, HashMap<String, Track>) {
+        let playlist = Playlist {
+            name: String::from("foobar"),
+            playlist_items: vec![PlaylistItem { track_id: String::from("1") }, PlaylistItem { track_id: String::from("2") }],
+            ..Default::default()
+        };
+
+        let tracks = HashMap::from([
+            (String::from("1"), Track {
+                persistent_id: Some(String::from("ABC123")),
+                name: Some(String::from("Favorite Song")),
+                location: Some(PathBuf::from("/foo/bar")),
+                duration: 3000,
+                artist: Some(String::from("dude")),
+                track_number: Some(2),
+                ..Default::default()
+            }),
+            (String::from("2"), Track {
+                name: Some(String::from("Least Favorite Song")),
+                location: None,
+                ..Default::default()
+            }),
+        ]);
+
+        (playlist, tracks)
+    }
+
+    #[test]
+    fn test_jspf_writer() {
+        let (playlist, tracks) = fixture();
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let args = ExportOptions {
+            music_path: Some(PathBuf::from("/baz")),
+            ..Default::default()
+        };
+
+        let (file_name, contents) = (JspfWriter.file_name(&playlist), JspfWriter.render(&ctx, &args, &tracks, &playlist));
+        assert_eq!(file_name, "foobar.jspf");
+        let parsed: Value = serde_json::from_str(&contents).unwrap();
+        assert_eq!(parsed, json!({
+            "playlist": {
+                "title": "foobar",
+                "track": [{
+                    "location": ["file:///baz/bar"],
+                    "identifier": ["urn:itunes:persistent-id:ABC123"],
+                    "title": "Favorite Song",
+                    "creator": "dude",
+                    "trackNum": 2,
+                    "duration": 3000,
+                }],
+            }
+        }));
+    }
+
+    #[test]
+    fn test_jspf_writer_relative() {
+        let (playlist, tracks) = fixture();
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/baz/Play Lists"));
+        let args = ExportOptions {
+            music_path: Some(PathBuf::from("/baz/Mü sic")),
+            relative: true,
+            ..Default::default()
+        };
+
+        let contents = JspfWriter.render(&ctx, &args, &tracks, &playlist);
+        let parsed: Value = serde_json::from_str(&contents).unwrap();
+        assert_eq!(parsed["playlist"]["track"][0]["location"], json!(["../M%C3%BC%20sic/bar"]));
+    }
+
+    #[test]
+    fn test_json_writer() {
+        let (playlist, tracks) = fixture();
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let args = ExportOptions::default();
+
+        let (file_name, contents) = (JsonWriter.file_name(&playlist), JsonWriter.render(&ctx, &args, &tracks, &playlist));
+        assert_eq!(file_name, "foobar.json");
+        let parsed: Value = serde_json::from_str(&contents).unwrap();
+        assert_eq!(parsed["schema_version"], json!(2));
+        assert_eq!(parsed["name"], json!("foobar"));
+        assert_eq!(parsed["playlist_items"], json!([{ "track_id": "1" }, { "track_id": "2" }]));
+        assert_eq!(parsed["folder"], json!(false));
+        assert_eq!(parsed["tracks"][0]["track_id"], json!("1"));
+        assert_eq!(parsed["tracks"][0]["resolved_location"], json!("/foo/bar"));
+        assert_eq!(parsed["tracks"][0]["location"], json!("/foo/bar"));
+        assert_eq!(parsed["tracks"][0]["album"], Value::Null);
+        assert_eq!(parsed["tracks"][1]["resolved_location"], Value::Null);
+        assert_eq!(parsed["tracks"][1]["name"], json!("Least Favorite Song"));
+    }
+
+    #[test]
+    fn test_json_writer_normalization() {
+        let (mut playlist, mut tracks) = fixture();
+        playlist.name = String::from("Cafe\u{301}");
+        let track = tracks.get_mut("1").unwrap();
+        track.artist = Some(String::from("Zoe\u{308}"));
+        track.location = Some(PathBuf::from("/foo/Zoe\u{308}.mp3"));
+        let ctx = LocationContext::new(&[], Path::new("/"));
+
+        let mut args = ExportOptions::default();
+        let parsed: Value =
+            serde_json::from_str(&JsonWriter.render(&ctx, &args, &tracks, &playlist)).unwrap();
+        assert_eq!(parsed["name"], json!("Caf\u{e9}"));
+        assert_eq!(parsed["tracks"][0]["artist"], json!("Zo\u{eb}"));
+        assert_eq!(parsed["tracks"][0]["location"], json!("/foo/Zo\u{eb}.mp3"));
+        assert_eq!(parsed["tracks"][0]["resolved_location"], json!("/foo/Zo\u{eb}.mp3"));
+
+        args.normalization = Normalization::None;
+        let parsed: Value =
+            serde_json::from_str(&JsonWriter.render(&ctx, &args, &tracks, &playlist)).unwrap();
+        assert_eq!(parsed["name"], json!("Cafe\u{301}"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_json_writer_non_utf8_location() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let (playlist, mut tracks) = fixture();
+        let location = Path::new(OsStr::from_bytes(b"/foo/caf\xe9.mp3")).to_path_buf();
+        tracks.get_mut("1").unwrap().location = Some(location);
+        let ctx = LocationContext::new(&[], Path::new("/"));
+
+        let contents = JsonWriter.render(&ctx, &ExportOptions::default(), &tracks, &playlist);
+        let parsed: Value = serde_json::from_str(&contents).unwrap();
+        assert_eq!(parsed["tracks"][0]["location"], json!("/foo/caf\u{fffd}.mp3"));
+    }
+}
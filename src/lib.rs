@@ -0,0 +1,87 @@
+//! playlistrs
+//!
+//! Reads the library XML exported by iTunes® or Music and converts its
+//! playlists into the formats other players understand.
+//!
+//! The usual flow is to load the library, work out where its tracks live,
+//! then render each playlist with the options for the target:
+//!
+//! ```no_run
+//! use std::path::{Path, PathBuf};
+//!
+//! use playlistrs::{
+//!     get_library_roots, load_library, render_playlist, ExportOptions, LocationContext,
+//!     WriterRegistry,
+//! };
+//!
+//! let library = load_library("Library.xml").expect("a library XML file");
+//! let roots = get_library_roots(&library);
+//! let options = ExportOptions {
+//!     music_path: Some(PathBuf::from("/mnt/music")),
+//!     ..Default::default()
+//! };
+//!
+//! let registry = WriterRegistry::default();
+//! let writer = registry.get("m3u8").expect("a built in format");
+//! let ctx = LocationContext::new(&roots, Path::new("/mnt/playlists"));
+//! for playlist in &library.playlists {
+//!     let (file_name, contents) =
+//!         render_playlist(writer, &ctx, &options, &library.tracks, playlist);
+//!     println!("{file_name}: {} bytes", contents.len());
+//! }
+//! ```
+//!
+//! To write every playlist to a directory the way the command line does,
+//! folders and duplicate names included, give an [`ExportJob`] to
+//! [`export_library`] and report from the [`ExportSummary`] it returns.
+//! Nothing is printed, progress and warnings go to the [`Log`] in the
+//! options if one is given.
+//!
+//! Other formats can be added by implementing [`PlaylistWriter`] and
+//! registering it, other library exports by implementing [`LibrarySource`]
+//! to read them into a [`Library`]. Single tracks can be located with
+//! [`resolve_path`] and written as a playlist entry with
+//! [`format_location`].
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+mod error;
+mod export;
+mod json;
+mod library;
+mod library_xml;
+mod m3u;
+mod normalize;
+mod path_utils;
+mod playlist_tree;
+mod pls;
+mod relocate;
+mod rewrite;
+mod sanitize;
+mod smart;
+mod smart_eval;
+mod smart_export;
+mod source;
+mod track;
+mod wpl;
+mod writer;
+mod xml;
+mod xspf;
+
+pub use error::PlaylisterError;
+pub use export::{
+    export_library, DuplicateMode, ExportJob, ExportOptions, ExportSummary, FolderMode, Log,
+    LogLevel, MissingMode,
+};
+pub use library::{get_library_roots, Library, Playlist, PlaylistItem, PlaylistKind};
+pub use library_xml::{load_library, ItunesXmlSource};
+pub use normalize::Normalization;
+pub use path_utils::{drive_of, format_location, portable_path, resolve_path, LocationContext};
+pub use relocate::{RelocateError, RelocationReport};
+pub use rewrite::{read_rewrite_file, ExtensionRule, RewriteError, RewriteRule};
+pub use sanitize::FsProfile;
+pub use smart_export::SmartFormat;
+pub use source::{LibrarySource, SourceError};
+pub use track::Track;
+pub use writer::{render_playlist, Encoding, PlaylistWriter, WriterRegistry};
@@ -0,0 +1,339 @@
+//! library
+//!
+//! The library model every source is read into and the writers work from:
+//! the tracks, the playlists and where the music lives.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
+
+use crate::path_utils::longest_common_prefix;
+use crate::smart::{decode_smart_playlist, SmartDecodeError, SmartPlaylist};
+use crate::track::Track;
+
+/// The built-in playlists a library creates, user created playlists are
+/// `User`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PlaylistKind {
+    #[default]
+    User,
+    Library,
+    Music,
+    Movies,
+    TvShows,
+    Podcasts,
+    Audiobooks,
+    Purchased,
+    Genius,
+    ItunesU,
+    MusicVideos,
+    HomeVideos,
+    Downloaded,
+    Hidden,
+    Other,
+}
+
+impl PlaylistKind {
+    pub fn from_arg(arg: &str) -> Option<PlaylistKind> {
+        match arg {
+            "library" => Some(PlaylistKind::Library),
+            "music" => Some(PlaylistKind::Music),
+            "movies" => Some(PlaylistKind::Movies),
+            "tv-shows" => Some(PlaylistKind::TvShows),
+            "podcasts" => Some(PlaylistKind::Podcasts),
+            "audiobooks" => Some(PlaylistKind::Audiobooks),
+            "purchased" => Some(PlaylistKind::Purchased),
+            "genius" => Some(PlaylistKind::Genius),
+            "itunes-u" => Some(PlaylistKind::ItunesU),
+            "music-videos" => Some(PlaylistKind::MusicVideos),
+            "home-videos" => Some(PlaylistKind::HomeVideos),
+            "downloaded" => Some(PlaylistKind::Downloaded),
+            "hidden" => Some(PlaylistKind::Hidden),
+            "other" => Some(PlaylistKind::Other),
+            _ => None,
+        }
+    }
+}
+
+pub struct PlaylistItem {
+    /// The key of the track in [`Library::tracks`].
+    pub track_id: String,
+}
+
+#[derive(Default)]
+pub struct Playlist {
+    pub name: String,
+    pub playlist_items: Vec<PlaylistItem>,
+    pub persistent_id: Option<String>,
+
+    /// The folder the playlist is in, if any.
+    pub parent_persistent_id: Option<String>,
+
+    /// Folders carry the merged items of everything inside them.
+    pub folder: bool,
+
+    pub kind: PlaylistKind,
+
+    /// The rules of a smart playlist, None for regular playlists. Rules
+    /// that couldn't be decoded are kept as the error so they're reported
+    /// where the rules would have been used. Set with `set_smart_rules`.
+    pub(crate) smart: Option<Result<SmartPlaylist, SmartDecodeError>>,
+}
+
+impl Playlist {
+    /// Makes this a smart playlist from the rules as iTunes® encodes them,
+    /// the Smart Info and Smart Criteria data of the library XML. Rules
+    /// kept any other way can't be given, those playlists are written with
+    /// the tracks they have.
+    pub fn set_smart_rules(&mut self, info: &[u8], criteria: &[u8]) {
+        self.smart = Some(decode_smart_playlist(info, criteria));
+    }
+
+    pub fn is_smart(&self) -> bool {
+        self.smart.is_some()
+    }
+}
+
+pub struct Library {
+    /// The folder the library keeps its media in, if it has one.
+    pub music_folder: Option<PathBuf>,
+
+    /// Keyed by the track ID the playlist items refer to.
+    pub tracks: HashMap<String, Track>,
+
+    /// In the order the library lists them, folders included.
+    pub playlists: Vec<Playlist>,
+}
+
+impl Library {
+    /// The tracks of a playlist in order, skipping any that aren't in the
+    /// library.
+    pub fn playlist_tracks<'a>(&'a self, playlist: &'a Playlist) -> impl Iterator<Item = &'a Track> {
+        playlist
+            .playlist_items
+            .iter()
+            .filter_map(|item| self.tracks.get(&item.track_id))
+    }
+}
+
+// iTunes/Music sorts the media folder into these by kind, tracks are
+// relative to them rather than the media folder itself.
+static MEDIA_KIND_FOLDERS: [&str; 10] = [
+    "Music",
+    "Movies",
+    "TV Shows",
+    "Podcasts",
+    "Audiobooks",
+    "Books",
+    "Home Videos",
+    "Music Videos",
+    "iTunes U",
+    "Tones",
+];
+
+// Mount points where the volume name is the second component.
+static MOUNT_DIRS: [&str; 3] = ["Volumes", "mnt", "media"];
+
+fn locations(lib_xml: &Library) -> impl Iterator<Item = &Path> {
+    lib_xml
+        .tracks
+        .values()
+        .filter_map(|track| track.location.as_deref())
+}
+
+fn media_root(music_folder: &Path, location: &Path) -> Option<PathBuf> {
+    let relative = location.strip_prefix(music_folder).ok()?;
+    let mut components = relative.components();
+    let first = components.next()?;
+    match first {
+        // Only a media kind folder if the track is inside it.
+        Component::Normal(name)
+            if components.next().is_some()
+                && MEDIA_KIND_FOLDERS.iter().any(|kind| name == *kind) =>
+        {
+            Some(music_folder.join(name))
+        }
+        _ => Some(music_folder.to_path_buf()),
+    }
+}
+
+// Groups paths by the drive they're on, e.g. /Volumes/Ext or /Users.
+fn volume_of(location: &Path) -> PathBuf {
+    let mut volume = PathBuf::new();
+    let mut names = 0;
+    for component in location.components() {
+        if let Component::Normal(name) = component {
+            let is_mount = names == 0 && MOUNT_DIRS.iter().any(|dir| name == *dir);
+            names += 1;
+            volume.push(name);
+            if !is_mount {
+                break;
+            }
+        } else {
+            volume.push(component);
+        }
+    }
+
+    volume
+}
+
+/// Finds the directories the library's tracks live in. The Music Folder
+/// from the library, split by media kind, comes first. Tracks outside of it
+/// are grouped by volume and each group contributes the longest directory
+/// all of its tracks share.
+pub fn get_library_roots(lib_xml: &Library) -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+    let mut outside = BTreeMap::<PathBuf, Vec<&Path>>::new();
+    for location in locations(lib_xml) {
+        let root = lib_xml
+            .music_folder
+            .as_ref()
+            .and_then(|folder| media_root(folder, location));
+
+        match root {
+            Some(root) if !roots.contains(&root) => roots.push(root),
+            Some(_) => {}
+            None => outside
+                .entry(volume_of(location))
+                .or_default()
+                .push(location),
+        }
+    }
+
+    roots.sort();
+    for group in outside.values() {
+        let parents = group.iter().filter_map(|location| location.parent());
+        if let Some(prefix) = longest_common_prefix(parents) {
+            roots.push(prefix);
+        }
+    }
+
+    roots
+}
+
+/// Tracks that aren't in the library's Music Folder. Empty if the library
+/// doesn't say where its Music Folder is.
+pub fn tracks_outside_music_folder(lib_xml: &Library) -> Vec<&Track> {
+    let folder = match &lib_xml.music_folder {
+        Some(folder) => folder,
+        None => return Vec::new(),
+    };
+
+    let mut outside = lib_xml
+        .tracks
+        .values()
+        .filter(|track| {
+            track
+                .location
+                .as_ref()
+                .is_some_and(|location| !location.starts_with(folder))
+        })
+        .collect::<Vec<&Track>>();
+
+    outside.sort_by_key(|track| track.get_name());
+    outside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smart::tests::{criteria_blob, info_blob, text_rule};
+
+    fn library(music_folder: Option<&str>, locations: &[&str]) -> Library {
+        let tracks = locations
+            .iter()
+            .enumerate()
+            .map(|(i, location)| {
+                let track = Track {
+                    location: Some(PathBuf::from(location)),
+                    ..Default::default()
+                };
+                (i.to_string(), track)
+            })
+            .collect();
+
+        Library {
+            music_folder: music_folder.map(PathBuf::from),
+            tracks,
+            playlists: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_set_smart_rules() {
+        let mut playlist = Playlist::default();
+        assert!(!playlist.is_smart());
+
+        let criteria = criteria_blob(false, &[text_rule(0x08, 0x02, false, "Rock")]);
+        playlist.set_smart_rules(&info_blob(None, false), &criteria);
+        assert!(playlist.is_smart());
+        assert!(matches!(playlist.smart, Some(Ok(_))));
+
+        playlist.set_smart_rules(b"not", b"rules");
+        assert!(matches!(playlist.smart, Some(Err(_))));
+    }
+
+    #[test]
+    fn test_get_library_roots() {
+        let lib = library(
+            Some("/Users/me/Music/iTunes/iTunes Media"),
+            &[
+                "/Users/me/Music/iTunes/iTunes Media/Music/Artist/Album/a.m4a",
+                "/Users/me/Music/iTunes/iTunes Media/Podcasts/Show/b.mp3",
+                "/Users/me/Music/iTunes/iTunes Media/Loose.mp3",
+                "/Volumes/Ext/Music/Artist/Album/c.mp3",
+                "/Volumes/Ext/Music/Other/Album/d.mp3",
+                "/Volumes/NAS/share/e.mp3",
+            ],
+        );
+
+        assert_eq!(
+            get_library_roots(&lib),
+            vec![
+                PathBuf::from("/Users/me/Music/iTunes/iTunes Media"),
+                PathBuf::from("/Users/me/Music/iTunes/iTunes Media/Music"),
+                PathBuf::from("/Users/me/Music/iTunes/iTunes Media/Podcasts"),
+                PathBuf::from("/Volumes/Ext/Music"),
+                PathBuf::from("/Volumes/NAS/share"),
+            ]
+        );
+
+        let outside = tracks_outside_music_folder(&lib);
+        assert_eq!(outside.len(), 3);
+    }
+
+    #[test]
+    fn test_get_library_roots_no_music_folder() {
+        let lib = library(
+            None,
+            &[
+                "/Users/me/Music/Artist/Album/a.m4a",
+                "/Users/me/Music/Other/Album/b.m4a",
+            ],
+        );
+
+        assert_eq!(get_library_roots(&lib), vec![PathBuf::from("/Users/me/Music")]);
+        assert!(tracks_outside_music_folder(&lib).is_empty());
+        assert!(get_library_roots(&library(None, &[])).is_empty());
+    }
+
+    #[test]
+    fn test_playlist_tracks() {
+        let lib = library(None, &["/Music/a.mp3", "/Music/b.mp3"]);
+        let playlist = Playlist {
+            playlist_items: ["1", "7", "0"]
+                .into_iter()
+                .map(|id| PlaylistItem { track_id: String::from(id) })
+                .collect(),
+            ..Default::default()
+        };
+
+        let locations = lib
+            .playlist_tracks(&playlist)
+            .filter_map(|track| track.location.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(locations, [Path::new("/Music/b.mp3"), Path::new("/Music/a.mp3")]);
+    }
+}
@@ -6,51 +6,334 @@
 //! @author jasmith79
 //! @license MIT
 //! @copyright 2023
-use anyhow::{Context, Result};
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
 
-use crate::path_utils::generate_itunes_prefix;
+use crate::library::{Library, Playlist, PlaylistItem, PlaylistKind};
+use crate::path_utils::deserialize_path;
+use crate::smart::deserialize_data;
+use crate::source::{LibrarySource, SourceError};
 use crate::track::Track;
 
 #[derive(Deserialize)]
-pub struct PlaylistItem {
+struct XmlPlaylistItem {
     #[serde(rename = "Track ID")]
-    pub track_id: i32,
+    track_id: i32,
 }
 
+/// A playlist as iTunes/Music writes it, the built-in playlists are
+/// identified by flags rather than by name since the names are localized.
 #[derive(Deserialize)]
-pub struct Playlist {
+struct XmlPlaylist {
     #[serde(rename = "Name")]
-    pub name: String,
+    name: String,
 
+    // Empty playlists have no items key at all.
     #[serde(rename = "Playlist Items")]
-    pub playlist_items: Vec<PlaylistItem>,
+    #[serde(default)]
+    playlist_items: Vec<XmlPlaylistItem>,
+
+    #[serde(rename = "Playlist Persistent ID")]
+    #[serde(default)]
+    persistent_id: Option<String>,
+
+    #[serde(rename = "Parent Persistent ID")]
+    #[serde(default)]
+    parent_persistent_id: Option<String>,
+
+    // Folders carry the merged items of everything inside them.
+    #[serde(rename = "Folder")]
+    #[serde(default)]
+    folder: bool,
+
+    #[serde(rename = "Master")]
+    #[serde(default)]
+    master: bool,
+
+    #[serde(rename = "Visible")]
+    #[serde(default = "default_visible")]
+    visible: bool,
+
+    #[serde(rename = "Distinguished Kind")]
+    #[serde(default)]
+    distinguished_kind: Option<i32>,
+
+    #[serde(rename = "Music")]
+    #[serde(default)]
+    music: bool,
+
+    #[serde(rename = "Movies")]
+    #[serde(default)]
+    movies: bool,
+
+    #[serde(rename = "TV Shows")]
+    #[serde(default)]
+    tv_shows: bool,
+
+    #[serde(rename = "Podcasts")]
+    #[serde(default)]
+    podcasts: bool,
+
+    #[serde(rename = "Audiobooks")]
+    #[serde(default)]
+    audiobooks: bool,
+
+    #[serde(rename = "Purchased Music")]
+    #[serde(default)]
+    purchased_music: bool,
+
+    #[serde(rename = "Genius Track ID")]
+    #[serde(default)]
+    genius_track_id: Option<i32>,
+
+    #[serde(rename = "Smart Info")]
+    #[serde(deserialize_with = "deserialize_data")]
+    #[serde(default)]
+    smart_info: Option<Vec<u8>>,
+
+    #[serde(rename = "Smart Criteria")]
+    #[serde(deserialize_with = "deserialize_data")]
+    #[serde(default)]
+    smart_criteria: Option<Vec<u8>>,
+}
+
+// These are the values seen in the wild, anything else with a
+// Distinguished Kind is still built-in, we just don't know what it is.
+fn from_distinguished_kind(kind: i32) -> PlaylistKind {
+    match kind {
+        2 => PlaylistKind::Movies,
+        3 => PlaylistKind::TvShows,
+        4 => PlaylistKind::Music,
+        5 => PlaylistKind::Audiobooks,
+        10 => PlaylistKind::Podcasts,
+        19 => PlaylistKind::Purchased,
+        26 => PlaylistKind::Genius,
+        31 => PlaylistKind::ItunesU,
+        47 => PlaylistKind::MusicVideos,
+        48 => PlaylistKind::HomeVideos,
+        65 => PlaylistKind::Downloaded,
+        _ => PlaylistKind::Other,
+    }
+}
+fn default_visible() -> bool {
+    true
+}
+
+impl Default for XmlPlaylist {
+    fn default() -> XmlPlaylist {
+        XmlPlaylist {
+            name: String::new(),
+            playlist_items: Vec::new(),
+            persistent_id: None,
+            parent_persistent_id: None,
+            folder: false,
+            master: false,
+            visible: true,
+            distinguished_kind: None,
+            music: false,
+            movies: false,
+            tv_shows: false,
+            podcasts: false,
+            audiobooks: false,
+            purchased_music: false,
+            genius_track_id: None,
+            smart_info: None,
+            smart_criteria: None,
+        }
+    }
+}
+
+impl XmlPlaylist {
+    fn kind(&self) -> PlaylistKind {
+        if self.master {
+            PlaylistKind::Library
+        } else if let Some(kind) = self.distinguished_kind {
+            from_distinguished_kind(kind)
+        } else if self.music {
+            PlaylistKind::Music
+        } else if self.movies {
+            PlaylistKind::Movies
+        } else if self.tv_shows {
+            PlaylistKind::TvShows
+        } else if self.podcasts {
+            PlaylistKind::Podcasts
+        } else if self.audiobooks {
+            PlaylistKind::Audiobooks
+        } else if self.purchased_music {
+            PlaylistKind::Purchased
+        } else if self.genius_track_id.is_some() {
+            PlaylistKind::Genius
+        } else if !self.visible {
+            PlaylistKind::Hidden
+        } else {
+            PlaylistKind::User
+        }
+    }
+}
+
+impl From<XmlPlaylist> for Playlist {
+    fn from(playlist: XmlPlaylist) -> Playlist {
+        let kind = playlist.kind();
+        let mut converted = Playlist {
+            name: playlist.name,
+            playlist_items: playlist
+                .playlist_items
+                .into_iter()
+                .map(|item| PlaylistItem {
+                    track_id: item.track_id.to_string(),
+                })
+                .collect(),
+            persistent_id: playlist.persistent_id,
+            parent_persistent_id: playlist.parent_persistent_id,
+            folder: playlist.folder,
+            kind,
+            smart: None,
+        };
+
+        if let (Some(info), Some(criteria)) = (&playlist.smart_info, &playlist.smart_criteria) {
+            converted.set_smart_rules(info, criteria);
+        }
+
+        converted
+    }
 }
 
 #[derive(Deserialize)]
-pub struct LibraryXMLData {
+struct LibraryXMLData {
+    #[serde(rename = "Music Folder")]
+    #[serde(deserialize_with = "deserialize_path")]
+    #[serde(default)]
+    music_folder: Option<PathBuf>,
+
     #[serde(rename = "Tracks")]
-    pub tracks: HashMap<String, Track>,
+    tracks: HashMap<String, Track>,
 
     #[serde(rename = "Playlists")]
-    pub playlists: Vec<Playlist>,
-}
-
-pub fn get_itunes_prefix(lib_xml: &LibraryXMLData) -> Result<&Path> {
-    let first_list = &lib_xml.playlists[0];
-    let first_track_record = &first_list.playlist_items[0];
-    let first_id = first_track_record.track_id.to_string();
-    let first_track = lib_xml
-        .tracks
-        .get(&first_id)
-        .context("Cannot find first track")?;
-    let first_location = first_track
-        .location
-        .as_ref()
-        .context("First track has no location")?;
-    let prefix = generate_itunes_prefix(first_location)?;
-    Ok(prefix)
+    playlists: Vec<XmlPlaylist>,
+}
+
+impl From<LibraryXMLData> for Library {
+    fn from(data: LibraryXMLData) -> Library {
+        Library {
+            music_folder: data.music_folder,
+            tracks: data.tracks,
+            playlists: data.playlists.into_iter().map(Playlist::from).collect(),
+        }
+    }
+}
+
+/// Library XML files exported by iTunes® or Music.
+pub struct ItunesXmlSource;
+
+impl LibrarySource for ItunesXmlSource {
+    fn name(&self) -> &'static str {
+        "iTunes® library XML"
+    }
+
+    fn load(&self, path: &Path) -> Result<Library, SourceError> {
+        plist::from_file::<_, LibraryXMLData>(path)
+            .map(Library::from)
+            .map_err(|err| SourceError(format!("Could not read {}: {err}", path.display())))
+    }
+}
+
+/// Reads a library XML file exported by iTunes® or Music.
+pub fn load_library<P>(path: &P) -> Result<Library, SourceError>
+where
+    P: AsRef<Path> + ?Sized,
+{
+    ItunesXmlSource.load(path.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playlist_kind() {
+        let user = XmlPlaylist::default();
+        assert_eq!(user.kind(), PlaylistKind::User);
+
+        let master = XmlPlaylist {
+            master: true,
+            visible: false,
+            ..Default::default()
+        };
+        assert_eq!(master.kind(), PlaylistKind::Library);
+
+        let music = XmlPlaylist {
+            name: String::from("Musique"),
+            distinguished_kind: Some(4),
+            music: true,
+            ..Default::default()
+        };
+        assert_eq!(music.kind(), PlaylistKind::Music);
+
+        let unknown = XmlPlaylist {
+            distinguished_kind: Some(9999),
+            ..Default::default()
+        };
+        assert_eq!(unknown.kind(), PlaylistKind::Other);
+
+        let podcasts = XmlPlaylist {
+            podcasts: true,
+            ..Default::default()
+        };
+        assert_eq!(podcasts.kind(), PlaylistKind::Podcasts);
+
+        let hidden = XmlPlaylist {
+            visible: false,
+            ..Default::default()
+        };
+        assert_eq!(hidden.kind(), PlaylistKind::Hidden);
+    }
+
+    #[test]
+    fn test_itunes_xml_source() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple Computer//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Music Folder</key><string>file:///Users/me/Music/</string>
+    <key>Tracks</key>
+    <dict>
+        <key>7</key>
+        <dict>
+            <key>Name</key><string>Favorite Song</string>
+            <key>Location</key><string>file:///Users/me/Music/Favorite%20Song.mp3</string>
+        </dict>
+    </dict>
+    <key>Playlists</key>
+    <array>
+        <dict>
+            <key>Name</key><string>Mix</string>
+            <key>Playlist Items</key>
+            <array>
+                <dict><key>Track ID</key><integer>7</integer></dict>
+            </array>
+        </dict>
+    </array>
+</dict>
+</plist>"#;
+
+        let path = std::env::temp_dir().join(format!("playlistrs-source-{}.xml", std::process::id()));
+        std::fs::write(&path, xml).unwrap();
+        let library = ItunesXmlSource.load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let library = library.unwrap();
+        assert_eq!(library.music_folder, Some(PathBuf::from("/Users/me/Music/")));
+        let playlist = &library.playlists[0];
+        assert_eq!(playlist.playlist_items[0].track_id, "7");
+        assert_eq!(playlist.kind, PlaylistKind::User);
+        assert!(playlist.smart.is_none());
+        let tracks = library.playlist_tracks(&library.playlists[0]).collect::<Vec<_>>();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].location, Some(PathBuf::from("/Users/me/Music/Favorite Song.mp3")));
+
+        let err = ItunesXmlSource.load(Path::new("/no/such/Library.xml")).err().unwrap();
+        assert!(err.to_string().starts_with("Could not read /no/such/Library.xml"));
+    }
 }
@@ -1,107 +1,153 @@
 //! m3u
 //!
-//! Contains functions pertaining to the creation of the m3u playlist format.
+//! Contains functions pertaining to the creation of the m3u and extended
+//! m3u8 playlist formats.
 //!
 //! @author jasmith79
 //! @license MIT
 //! @copyright 2023
-use std::borrow::Cow;
-use std::collections::HashMap;
-use std::path::Path;
-
-use partial_application::partial;
-use url::Url;
-
-use crate::cli::PlaylisterArgs;
-use crate::library_xml::Playlist;
+use crate::export::ExportOptions;
+use crate::library::Playlist;
+use crate::path_utils::{
+    find_artwork, format_location, resolve_path, verify_path, LocationContext,
+};
 use crate::track::Track;
+use crate::writer::PlaylistWriter;
 
-fn to_m3u_track<P>(itunes_prefix: &P, args: &PlaylisterArgs, track: &Track) -> Option<String>
-where
-    P: AsRef<Path> + ?Sized,
-{
-    let name = track.get_name();
+fn to_m3u_track(ctx: &LocationContext, args: &ExportOptions, track: &Track) -> Option<String> {
+    let name = args.normalization.apply(track.get_name());
     if args.verbose > 3 {
-        println!("Converting track {name}");
-    }
-
-    let loc = track.location.as_ref()?;
-    // If no music path use the original location, the inner
-    // option tracks the success of changing the path so we
-    // are sure to surface the None if the operation fails rather
-    // than .or-ing back in the original location.
-    let path = args
-        .music_path
-        .as_ref()
-        .map(|mpath| {
-            loc.strip_prefix(itunes_prefix).map_or_else(
-                |err| {
-                    if args.verbose > 0 {
-                        eprintln!("Track {name} does not match the iTunes prefix");
-                    }
-
-                    if args.verbose > 2 {
-                        eprintln!("Error: {err}");
-                    }
-
-                    None
-                },
-                |stripped| Some(Cow::Owned(mpath.join(stripped))),
-            )
-        })
-        .or(Some(Some(Cow::Borrowed(loc))))??;
-
-    let location = if args.use_file_url {
-        Cow::Owned(Url::from_file_path(path.as_ref()).ok()?.to_string())
-    } else {
-        Cow::Borrowed(path.to_str()?)
-    };
-
-    let artist = track.get_artist();
+        args.log.info(&format!("Converting track {name}"));
+    }
+
+    let path = resolve_path(ctx, args, track)?;
+    let missing = verify_path(ctx, args, &path)?;
+    let location = format_location(ctx, args, &path)?;
+    let artist = args.normalization.apply(track.get_artist());
     let duration = track.duration / 1000; // iTunes uses ms but m3u uses seconds
 
-    Some(format!(
+    let entry = format!(
         "#EXTINF:{},{} - {}\n{}",
         duration, artist, name, location
-    ))
+    );
+    Some(if missing { comment_out(&entry) } else { entry })
+}
+
+fn to_m3u8_track(ctx: &LocationContext, args: &ExportOptions, track: &Track) -> Option<String> {
+    let name = args.normalization.apply(track.get_name());
+    if args.verbose > 3 {
+        args.log.info(&format!("Converting track {name}"));
+    }
+
+    let path = resolve_path(ctx, args, track)?;
+    let missing = verify_path(ctx, args, &path)?;
+    let location = format_location(ctx, args, &path)?;
+    let artist = args.normalization.apply(track.get_artist());
+    let duration = track.duration / 1000;
+
+    let mut lines = vec![format!("#EXTINF:{},{} - {}", duration, artist, name)];
+    if let Some(album) = &track.album {
+        lines.push(format!("#EXTALB:{}", args.normalization.apply(album)));
+    }
+
+    // #EXTART is the album artist, the same for every track on the album.
+    let album_artist = track.album_artist.as_deref().unwrap_or(track.get_artist());
+    lines.push(format!("#EXTART:{}", args.normalization.apply(album_artist)));
+    if let Some(genre) = &track.genre {
+        lines.push(format!("#EXTGENRE:{}", args.normalization.apply(genre)));
+    }
+
+    // Artwork isn't in the library XML so the best we can do is look for
+    // the usual image files alongside the track. That means touching the
+    // disk, so only when --verify is already checking the files.
+    if let Some(artwork) = args.verify.then(|| find_artwork(&path)).flatten() {
+        if let Some(image) = format_location(ctx, args, &artwork) {
+            lines.push(format!("#EXTIMG:{image}"));
+        }
+    }
+
+    lines.push(location.into_owned());
+    let entry = lines.join("\n");
+    Some(if missing { comment_out(&entry) } else { entry })
 }
 
-pub fn to_m3u_playlist<P>(
-    itunes_prefix: &P,
-    args: &PlaylisterArgs,
-    tracks: &HashMap<String, Track>,
-    playlist: &Playlist,
-) -> (String, String)
-where
-    P: AsRef<Path> + ?Sized,
-{
-    let process_track = partial!(to_m3u_track, itunes_prefix, args, _);
-    let playlist_tracks = playlist
-        .playlist_items
-        .iter()
-        .filter_map(|item| {
-            // NOTE: no way I know of to avoid the .to_string(): the
-            // track_id in PlaylistItem matches to the track_id in the
-            // tracks HashMap but serde will panic if you try to do i32 in
-            // the HashMap or String for the PlaylistItem.
-            let id = &item.track_id.to_string();
-            tracks.get(id).and_then(process_track)
-        })
+/// Turns every line of an entry into a plain comment so players skip the
+/// directives along with the location.
+fn comment_out(entry: &str) -> String {
+    entry
+        .lines()
+        .map(|line| format!("# {line}"))
         .collect::<Vec<String>>()
-        .join("\n");
+        .join("\n")
+}
+
+/// Plain m3u, one `#EXTINF` line per track.
+pub struct M3uWriter;
+
+impl PlaylistWriter for M3uWriter {
+    fn name(&self) -> &'static str {
+        "m3u"
+    }
+
+    fn extension(&self) -> &str {
+        "m3u"
+    }
+
+    fn header(&self, options: &ExportOptions, playlist: &Playlist, _entries: &[String]) -> String {
+        format!("#EXTM3U\n#name={}\n", options.normalization.apply(&playlist.name))
+    }
 
-    let file_name = [&playlist.name, "m3u"].join(".");
-    (
-        file_name,
-        format!("#EXTM3U\n#name={}\n{}", playlist.name, playlist_tracks),
-    )
+    fn entry(
+        &self,
+        ctx: &LocationContext,
+        options: &ExportOptions,
+        track: &Track,
+        _number: usize,
+    ) -> Option<String> {
+        to_m3u_track(ctx, options, track)
+    }
+}
+
+/// Extended m3u with album, artist, genre and artwork directives.
+pub struct M3u8Writer;
+
+impl PlaylistWriter for M3u8Writer {
+    fn name(&self) -> &'static str {
+        "m3u8"
+    }
+
+    fn extension(&self) -> &str {
+        "m3u8"
+    }
+
+    // m3u8 is by definition UTF-8, which Rust strings already are, so
+    // there's nothing to transcode here.
+    fn header(&self, options: &ExportOptions, playlist: &Playlist, _entries: &[String]) -> String {
+        format!(
+            "#EXTM3U\n#EXTENC:UTF-8\n#PLAYLIST:{}\n",
+            options.normalization.apply(&playlist.name)
+        )
+    }
+
+    fn entry(
+        &self,
+        ctx: &LocationContext,
+        options: &ExportOptions,
+        track: &Track,
+        _number: usize,
+    ) -> Option<String> {
+        to_m3u8_track(ctx, options, track)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
     use super::*;
-    use crate::library_xml::PlaylistItem;
+    use crate::export::MissingMode;
+    use crate::library::PlaylistItem;
+    use crate::normalize::Normalization;
 
     #[test]
     fn test_to_m3u_track_basic() {
@@ -112,19 +158,19 @@ mod tests {
             artist: Some(String::from("dude")),
             album_artist: None,
             composer: None,
+            ..Default::default()
         };
 
-        let args = PlaylisterArgs {
+        let args = ExportOptions {
             music_path: Some(PathBuf::from("/baz")),
             verbose: 1,
             use_file_url: false,
-            path: PathBuf::from("/some/path"),
-            output_path: PathBuf::from("/whatever"),
-            include_default_playlists: false,
+            ..Default::default()
         };
 
-        let prefix = Path::new("/foo");
-        let result = to_m3u_track(&prefix, &args, &track);
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let result = to_m3u_track(&ctx, &args, &track);
         assert_eq!(result, Some(String::from("#EXTINF:3,dude - Favorite Song\n/baz/bar")));
     }
 
@@ -137,19 +183,19 @@ mod tests {
             artist: Some(String::from("dude")),
             album_artist: None,
             composer: None,
+            ..Default::default()
         };
 
-        let args = PlaylisterArgs {
+        let args = ExportOptions {
             music_path: Some(PathBuf::from("/baz")),
             verbose: 1,
             use_file_url: true,
-            path: PathBuf::from("/some/path"),
-            output_path: PathBuf::from("/whatever"),
-            include_default_playlists: false,
+            ..Default::default()
         };
 
-        let prefix = Path::new("/foo");
-        let result = to_m3u_track(&prefix, &args, &track);
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let result = to_m3u_track(&ctx, &args, &track);
         assert_eq!(result, Some(String::from("#EXTINF:3,dude - Favorite Song\nfile:///baz/bar")));
     }
 
@@ -162,19 +208,19 @@ mod tests {
             artist: Some(String::from("dude")),
             album_artist: None,
             composer: None,
+            ..Default::default()
         };
 
-        let args = PlaylisterArgs {
+        let args = ExportOptions {
             music_path: Some(PathBuf::from("/baz")),
             verbose: 1,
             use_file_url: true,
-            path: PathBuf::from("/some/path"),
-            output_path: PathBuf::from("/whatever"),
-            include_default_playlists: false,
+            ..Default::default()
         };
 
-        let prefix = Path::new("/foo");
-        let result = to_m3u_track(&prefix, &args, &track);
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let result = to_m3u_track(&ctx, &args, &track);
         assert_eq!(result, None);
     }
 
@@ -187,37 +233,37 @@ mod tests {
             artist: Some(String::from("dude")),
             album_artist: None,
             composer: None,
+            ..Default::default()
         };
 
-        let args = PlaylisterArgs {
+        let args = ExportOptions {
             music_path: None,
             verbose: 1,
             use_file_url: true,
-            path: PathBuf::from("/some/path"),
-            output_path: PathBuf::from("/whatever"),
-            include_default_playlists: false,
+            ..Default::default()
         };
 
-        let prefix = Path::new("/foo");
-        let result = to_m3u_track(&prefix, &args, &track);
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let result = to_m3u_track(&ctx, &args, &track);
         assert_eq!(result, Some(String::from("#EXTINF:3,dude - Favorite Song\nfile:///foo/bar")));
     }
 
     #[test]
-    fn test_to_m3u_playlist() {
-        let prefix = Path::new("/foo");
+    fn test_m3u_writer() {
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
         let playlist = Playlist {
             name: String::from("foobar"),
-            playlist_items: vec![PlaylistItem { track_id: 1 }, PlaylistItem { track_id: 2 }],
+            playlist_items: vec![PlaylistItem { track_id: String::from("1") }, PlaylistItem { track_id: String::from("2") }],
+            ..Default::default()
         };
 
-        let args = PlaylisterArgs {
+        let args = ExportOptions {
             music_path: Some(PathBuf::from("/baz")),
             verbose: 1,
             use_file_url: true,
-            path: PathBuf::from("/some/path"),
-            output_path: PathBuf::from("/whatever"),
-            include_default_playlists: false,
+            ..Default::default()
         };
 
         let tracks = HashMap::from([
@@ -228,6 +274,7 @@ mod tests {
                 artist: Some(String::from("dude")),
                 album_artist: None,
                 composer: None,
+                ..Default::default()
             }),
             (String::from("2"), Track {
                 name: Some(String::from("Least Favorite Song")),
@@ -236,12 +283,158 @@ mod tests {
                 artist: Some(String::from("dude")),
                 album_artist: None,
                 composer: None,
+                ..Default::default()
             })
 
         ]);
 
         let expected = String::from("#EXTM3U\n#name=foobar\n#EXTINF:3,dude - Favorite Song\nfile:///baz/bar");
-        let result = to_m3u_playlist(&prefix, &args, &tracks, &playlist);
+        let result = (M3uWriter.file_name(&playlist), M3uWriter.render(&ctx, &args, &tracks, &playlist));
         assert_eq!(result, (String::from("foobar.m3u"), expected));
     }
+
+    #[test]
+    fn test_to_m3u8_track() {
+        let track = Track {
+            name: Some(String::from("Favorite Song")),
+            location: Some(PathBuf::from("/foo/bar")),
+            duration: 3000,
+            artist: Some(String::from("dude")),
+            album: Some(String::from("Greatest Hits")),
+            genre: Some(String::from("Rock")),
+            ..Default::default()
+        };
+
+        let args = ExportOptions {
+            music_path: Some(PathBuf::from("/baz")),
+            ..Default::default()
+        };
+
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let result = to_m3u8_track(&ctx, &args, &track);
+        assert_eq!(
+            result,
+            Some(String::from(
+                "#EXTINF:3,dude - Favorite Song\n#EXTALB:Greatest Hits\n#EXTART:dude\n#EXTGENRE:Rock\n/baz/bar"
+            ))
+        );
+
+        let track = Track {
+            album_artist: Some(String::from("Various Artists")),
+            ..track
+        };
+        let result = to_m3u8_track(&ctx, &args, &track).unwrap();
+        assert!(result.starts_with("#EXTINF:3,dude - Favorite Song\n"));
+        assert!(result.contains("\n#EXTART:Various Artists\n"));
+    }
+
+    #[test]
+    fn test_to_m3u8_track_artwork() {
+        let dir = std::env::temp_dir().join(format!("playlistrs-artwork-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("bar.mp3"), b"").unwrap();
+        std::fs::write(dir.join("cover.jpg"), b"").unwrap();
+        let track = Track {
+            name: Some(String::from("Favorite Song")),
+            location: Some(dir.join("bar.mp3")),
+            ..Default::default()
+        };
+
+        let mut args = ExportOptions::default();
+        let ctx = LocationContext::new(&[], Path::new("/"));
+        let unchecked = to_m3u8_track(&ctx, &args, &track).unwrap();
+        args.verify = true;
+        let checked = to_m3u8_track(&ctx, &args, &track).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(!unchecked.contains("#EXTIMG"));
+        assert!(checked.contains(&format!("#EXTIMG:{}", dir.join("cover.jpg").display())));
+    }
+
+    #[test]
+    fn test_m3u8_writer() {
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let playlist = Playlist {
+            name: String::from("foobar"),
+            playlist_items: vec![PlaylistItem { track_id: String::from("1") }],
+            ..Default::default()
+        };
+
+        let args = ExportOptions {
+            ..Default::default()
+        };
+
+        let tracks = HashMap::from([(String::from("1"), Track {
+            name: Some(String::from("Favorite Song")),
+            location: Some(PathBuf::from("/foo/bar")),
+            duration: 3000,
+            artist: Some(String::from("dude")),
+            ..Default::default()
+        })]);
+
+        let expected = String::from("#EXTM3U\n#EXTENC:UTF-8\n#PLAYLIST:foobar\n#EXTINF:3,dude - Favorite Song\n#EXTART:dude\n/foo/bar");
+        let result = (M3u8Writer.file_name(&playlist), M3u8Writer.render(&ctx, &args, &tracks, &playlist));
+        assert_eq!(result, (String::from("foobar.m3u8"), expected));
+    }
+
+    #[test]
+    fn test_to_m3u_track_missing() {
+        let track = Track {
+            name: Some(String::from("Favorite Song")),
+            location: Some(PathBuf::from("/no/such/bar.mp3")),
+            duration: 3000,
+            artist: Some(String::from("dude")),
+            ..Default::default()
+        };
+
+        let mut args = ExportOptions {
+            verify: true,
+            missing: MissingMode::Comment,
+            ..Default::default()
+        };
+
+        let ctx = LocationContext::new(&[], Path::new("/"));
+        let result = to_m3u_track(&ctx, &args, &track);
+        assert_eq!(
+            result,
+            Some(String::from("# #EXTINF:3,dude - Favorite Song\n# /no/such/bar.mp3"))
+        );
+
+        args.missing = MissingMode::Drop;
+        assert_eq!(to_m3u_track(&ctx, &args, &track), None);
+        assert_eq!(ctx.missing.borrow().len(), 2);
+    }
+
+    #[test]
+    fn test_to_m3u_track_normalization() {
+        // Café by Zoë, decomposed the way a library built on macOS has it.
+        let track = Track {
+            name: Some(String::from("Cafe\u{301}")),
+            location: Some(PathBuf::from("/foo/Zoe\u{308}/Cafe\u{301}.mp3")),
+            duration: 3000,
+            artist: Some(String::from("Zoe\u{308}")),
+            ..Default::default()
+        };
+
+        let mut args = ExportOptions::default();
+        let ctx = LocationContext::new(&[], Path::new("/"));
+        assert_eq!(
+            to_m3u_track(&ctx, &args, &track),
+            Some(String::from("#EXTINF:3,Zo\u{eb} - Caf\u{e9}\n/foo/Zo\u{eb}/Caf\u{e9}.mp3"))
+        );
+
+        args.normalization = Normalization::Nfd;
+        assert_eq!(
+            to_m3u_track(&ctx, &args, &track),
+            Some(String::from("#EXTINF:3,Zoe\u{308} - Cafe\u{301}\n/foo/Zoe\u{308}/Cafe\u{301}.mp3"))
+        );
+
+        args.normalization = Normalization::Ascii;
+        assert_eq!(
+            to_m3u_track(&ctx, &args, &track),
+            Some(String::from("#EXTINF:3,Zoe - Cafe\n/foo/Zoe/Cafe.mp3"))
+        );
+    }
 }
@@ -6,45 +6,109 @@
 //! @license MIT
 //! @copyright 2023
 mod cli;
-mod library_xml;
-mod m3u;
-mod path_utils;
-mod track;
+mod config;
 
 use std::process;
 
 use cli::parse_args;
-use library_xml::get_itunes_prefix;
-use m3u::to_m3u_playlist;
-use path_utils::write_to_file;
-use plist::from_file;
+use playlistrs::{
+    export_library, ExportSummary, ItunesXmlSource, LibrarySource, Log, LogLevel,
+    PlaylisterError, WriterRegistry,
+};
+
+/// Errors that stop the run before anything is written.
+fn exit_with(err: PlaylisterError) -> ! {
+    eprintln!("{err}");
+    process::exit(err.exit_code());
+}
+
+fn print_log(level: LogLevel, message: &str) {
+    match level {
+        LogLevel::Info => println!("{message}"),
+        LogLevel::Warning => eprintln!("{message}"),
+    }
+}
+
+fn report(summary: &ExportSummary, verify: bool, verbose: u8) {
+    for (name, missing) in &summary.missing {
+        eprintln!("Playlist {name} is missing {} track(s):", missing.len());
+        for path in missing {
+            eprintln!("    {}", path.display());
+        }
+    }
+
+    if verbose > 0 {
+        for (rule, hits) in &summary.rewrite_hits {
+            println!("Rewrite rule {rule} matched {hits} track(s).");
+        }
+    }
+
+    if let Some(relocation) = &summary.relocation {
+        println!(
+            "Relocated {} of {} missing track(s), {} low confidence match(es) to review.",
+            relocation.relocated, relocation.missing, relocation.suggested
+        );
+    }
+
+    if !summary.renamed.is_empty() {
+        println!("Renamed {} playlist(s) with duplicate names:", summary.renamed.len());
+        for (name, path) in &summary.renamed {
+            println!("    {name} -> {}", path.display());
+        }
+    }
+
+    println!("Wrote {} of {} playlist(s).", summary.written, summary.playlists);
+    if summary.unresolved > 0 {
+        println!(
+            "Left out {} track(s) that couldn't be resolved, -v lists them.",
+            summary.unresolved
+        );
+    }
+
+    if verify {
+        println!(
+            "Verified {} track(s), {} missing from {} playlist(s).",
+            summary.checked,
+            missing_count(summary),
+            summary.missing.len()
+        );
+    }
+}
+
+fn missing_count(summary: &ExportSummary) -> usize {
+    summary.missing.iter().map(|(_, missing)| missing.len()).sum()
+}
 
 fn main() {
-    let default_lists = vec!["Downloaded", "Library", "Music"];
-    let args = parse_args();
-    let library_data = from_file(&args.path).unwrap_or_else(|_err| {
-        eprintln!("Could not read XML plist file.");
-        process::exit(1);
-    });
-
-    let itunes_prefix = get_itunes_prefix(&library_data).unwrap_or_else(|err| {
-        eprintln!("Cannot extract iTunes path prefix from available track: {err}");
-        process::exit(2);
-    });
-
-    for playlist in &library_data.playlists {
-        let name: &str = &playlist.name;
-        if args.include_default_playlists || !default_lists.contains(&name) {
-            if args.verbose > 0 {
-                println!("Converting playlist {name}.");
-            }
-            let (name, contents) =
-                to_m3u_playlist(&itunes_prefix, &args, &library_data.tracks, playlist);
-            let path = args.output_path.join(name);
-            write_to_file(&args, &path, &contents);
-            if args.verbose > 1 {
-                println!("Done.");
-            }
+    let registry = WriterRegistry::default();
+    let mut args = parse_args(&registry).unwrap_or_else(|err| exit_with(err));
+    args.job.options.log = Log::new(print_log);
+    let writer = registry
+        .get(&args.format)
+        .expect("--format only accepts registered writers");
+    let source: &dyn LibrarySource = &ItunesXmlSource;
+    let library_data = source
+        .load(&args.path)
+        .unwrap_or_else(|err| exit_with(PlaylisterError::Library(err)));
+
+    let summary = export_library(writer, &library_data, &args.job)
+        .unwrap_or_else(|err| exit_with(err));
+    let verify = args.job.options.verify;
+    report(&summary, verify, args.job.options.verbose);
+
+    if let Some(err) = summary.failures.first() {
+        eprintln!("{} error(s):", summary.failures.len());
+        for err in &summary.failures {
+            eprintln!("    {err}");
         }
+        process::exit(err.exit_code());
+    }
+
+    if verify && !summary.missing.is_empty() {
+        let err = PlaylisterError::MissingTracks {
+            missing: missing_count(&summary),
+            playlists: summary.missing.len(),
+        };
+        process::exit(err.exit_code());
     }
 }
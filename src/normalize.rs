@@ -0,0 +1,138 @@
+//! normalize
+//!
+//! Unicode normalization of the paths and titles written to playlists.
+//! Names are kept as the library has them until they're written out, then
+//! composed (NFC, what most players and Linux filesystems expect),
+//! decomposed (NFD, like macOS and some Samba shares store them), left
+//! alone or transliterated to plain ASCII for players that can't cope.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::{is_nfc, is_nfd, UnicodeNormalization};
+
+static REPLACEMENT: char = '_';
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalization {
+    #[default]
+    Nfc,
+    Nfd,
+    None,
+    Ascii,
+}
+
+impl Normalization {
+    pub fn from_arg(arg: &str) -> Option<Normalization> {
+        match arg {
+            "nfc" => Some(Normalization::Nfc),
+            "nfd" => Some(Normalization::Nfd),
+            "none" => Some(Normalization::None),
+            "ascii" => Some(Normalization::Ascii),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        match self {
+            Normalization::Nfc if !is_nfc(text) => Cow::Owned(text.nfc().collect()),
+            Normalization::Nfd if !is_nfd(text) => Cow::Owned(text.nfd().collect()),
+            Normalization::Ascii if !text.is_ascii() => Cow::Owned(to_ascii(text)),
+            _ => Cow::Borrowed(text),
+        }
+    }
+
+    /// Paths that aren't valid UTF-8 are left as they are.
+    pub fn apply_path(self, path: &Path) -> Cow<'_, Path> {
+        match path.to_str().map(|text| self.apply(text)) {
+            Some(Cow::Owned(normalized)) => Cow::Owned(PathBuf::from(normalized)),
+            _ => Cow::Borrowed(path),
+        }
+    }
+}
+
+/// Strips accents and spells out the letters that don't decompose, e.g.
+/// Motörhead -> Motorhead and Straße -> Strasse. Anything else that isn't
+/// ASCII is replaced.
+fn to_ascii(text: &str) -> String {
+    let mut ascii = String::with_capacity(text.len());
+    for c in text.nfd().filter(|c| !is_combining_mark(*c)) {
+        let spelled = match c {
+            'ß' => "ss",
+            'æ' => "ae",
+            'Æ' => "AE",
+            'œ' => "oe",
+            'Œ' => "OE",
+            'ø' => "o",
+            'Ø' => "O",
+            'ł' => "l",
+            'Ł' => "L",
+            'đ' | 'ð' => "d",
+            'Đ' | 'Ð' => "D",
+            'þ' => "th",
+            'Þ' => "Th",
+            'ı' => "i",
+            '‘' | '’' | '′' => "'",
+            '“' | '”' | '″' => "\"",
+            '‐' | '‒' | '–' | '—' => "-",
+            '…' => "...",
+            '\u{a0}' => " ",
+            c if c.is_ascii() => {
+                ascii.push(c);
+                continue;
+            }
+            _ => {
+                ascii.push(REPLACEMENT);
+                continue;
+            }
+        };
+        ascii.push_str(spelled);
+    }
+
+    ascii
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Café and Zoë with the accents as separate combining characters, the
+    // way macOS filesystems store them.
+    static DECOMPOSED: &str = "Cafe\u{301} Zoe\u{308}";
+    static COMPOSED: &str = "Caf\u{e9} Zo\u{eb}";
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(Normalization::Nfc.apply(DECOMPOSED), COMPOSED);
+        assert_eq!(Normalization::Nfd.apply(COMPOSED), DECOMPOSED);
+        assert_eq!(Normalization::None.apply(DECOMPOSED), DECOMPOSED);
+        assert_eq!(Normalization::Ascii.apply(DECOMPOSED), "Cafe Zoe");
+        assert_eq!(Normalization::Ascii.apply(COMPOSED), "Cafe Zoe");
+        assert!(matches!(Normalization::Nfc.apply(COMPOSED), Cow::Borrowed(_)));
+        assert!(matches!(Normalization::Nfd.apply(DECOMPOSED), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_to_ascii() {
+        assert_eq!(to_ascii("Motörhead – Straße"), "Motorhead - Strasse");
+        assert_eq!(to_ascii("Sigur Rós: Ágætis byrjun"), "Sigur Ros: Agaetis byrjun");
+        assert_eq!(to_ascii("Don’t Stop…"), "Don't Stop...");
+        assert_eq!(to_ascii("坂本龍一"), "____");
+    }
+
+    #[test]
+    fn test_apply_path() {
+        let path = PathBuf::from(format!("/Music/{DECOMPOSED}/01 {DECOMPOSED}.mp3"));
+        let composed = Normalization::Nfc.apply_path(&path);
+        assert_eq!(composed, Path::new(&format!("/Music/{COMPOSED}/01 {COMPOSED}.mp3")));
+        assert_eq!(
+            Normalization::Ascii.apply_path(&path),
+            Path::new("/Music/Cafe Zoe/01 Cafe Zoe.mp3")
+        );
+        assert!(matches!(Normalization::None.apply_path(&path), Cow::Borrowed(_)));
+    }
+}
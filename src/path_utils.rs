@@ -5,87 +5,519 @@
 //! @author jasmith79
 //! @license MIT
 //! @copyright 2023
-use std::error::Error;
-use std::fmt;
-use std::fs::write;
-use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::ffi::OsString;
+use std::fs::{create_dir_all, write};
+use std::path::{Component, Path, PathBuf};
 
-use serde::{Deserialize, Deserializer};
-use unicode_normalization::UnicodeNormalization;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use url::Url;
 
-use crate::cli::PlaylisterArgs;
+use crate::error::PlaylisterError;
+use crate::export::{ExportOptions, MissingMode};
+use crate::relocate::Relocator;
+use crate::rewrite::{rewrite_path, swap_extension, RewriteHits};
+use crate::track::Track;
 
-#[derive(Debug)]
-pub struct PrefixError;
+/// Characters escaped in the segments of a relative URI reference. : is
+/// included so the first segment can't be mistaken for a scheme.
+static URI_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b':')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+static ARTWORK_FILE_NAMES: [&str; 6] = [
+    "cover.jpg",
+    "cover.png",
+    "folder.jpg",
+    "folder.png",
+    "front.jpg",
+    "AlbumArt.jpg",
+];
 
-impl Error for PrefixError {}
+/// The longest path that all of the given paths start with, compared by
+/// whole components.
+pub fn longest_common_prefix<'a, I>(paths: I) -> Option<PathBuf>
+where
+    I: IntoIterator<Item = &'a Path>,
+{
+    let mut paths = paths.into_iter();
+    let first = paths.next()?;
+    let mut unc = is_unc(first);
+    let mut prefix = first.components().collect::<Vec<Component>>();
+    for path in paths {
+        let shared = prefix
+            .iter()
+            .zip(path.components())
+            .take_while(|(a, b)| *a == b)
+            .count();
+        prefix.truncate(shared);
+        unc &= is_unc(path);
+    }
 
-impl fmt::Display for PrefixError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "Could not determine iTunes prefix from first track location"
-        )
+    // Components drop the second leading slash of a UNC path.
+    let prefix = prefix.iter().collect::<PathBuf>();
+    if unc {
+        let mut share = OsString::from("/");
+        share.push(prefix.as_os_str());
+        Some(PathBuf::from(share))
+    } else {
+        Some(prefix)
     }
 }
 
-pub fn generate_itunes_prefix<P>(path: &P) -> Result<&Path, PrefixError>
+pub fn deserialize_path<'de, D>(deserializer: D) -> Result<Option<PathBuf>, D::Error>
 where
-    P: AsRef<Path> + ?Sized,
+    D: Deserializer<'de>,
 {
-    let original = path.as_ref();
-    let mut prefix = original;
+    let de = String::deserialize(deserializer)?;
+    Ok(parse_location(&de))
+}
 
-    while !prefix.ends_with("Music") {
-        prefix = match prefix.parent() {
-            Some(p) => p,
-            None => return Err(PrefixError),
-        };
+/// Writes a path as text. Sources other than the library XML can have
+/// paths that aren't UTF-8, anything that isn't is replaced rather than
+/// failing the whole playlist.
+pub fn serialize_path<S>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    path.as_ref()
+        .map(|path| path.to_string_lossy())
+        .serialize(serializer)
+}
+
+/// Parses a library location url into a path. Locations from a Windows
+/// library are kept in a portable form so they can be rewritten and written
+/// back out on any platform: drive letter paths as C:/Users/... and UNC
+/// shares as //server/share/...
+pub fn parse_location(location: &str) -> Option<PathBuf> {
+    let url = Url::parse(location).ok()?;
+    if url.scheme() != "file" {
+        return None;
     }
 
-    Ok(prefix)
+    let segments = url
+        .path_segments()?
+        .map(|segment| percent_decode_str(segment).decode_utf8().ok())
+        .collect::<Option<Vec<Cow<str>>>>()?;
+
+    let host = url.host_str().filter(|host| *host != "localhost");
+    let path = match (host, segments.first()) {
+        (Some(host), _) => format!("//{host}/{}", segments.join("/")),
+        (None, Some(first)) if is_drive(first) => {
+            format!("{}:/{}", &first[..1], segments[1..].join("/"))
+        }
+        (None, _) => format!("/{}", segments.join("/")),
+    };
+
+    Some(PathBuf::from(path))
 }
 
-pub fn deserialize_path<'de, D>(deserializer: D) -> Result<Option<PathBuf>, D::Error>
+/// Reads a path written either POSIX or Windows style into the same form
+/// as parse_location, e.g. C:\Users\me -> C:/Users/me
+pub fn portable_path(path: &str) -> PathBuf {
+    let is_windows = path.starts_with("\\\\")
+        || path.get(..2).is_some_and(is_drive) && path[2..].starts_with(['\\', '/']);
+
+    if is_windows {
+        PathBuf::from(path.replace('\\', "/"))
+    } else {
+        PathBuf::from(path)
+    }
+}
+
+/// Matches a drive letter like C: (or the legacy C| form in urls).
+fn is_drive(part: &str) -> bool {
+    let mut chars = part.chars();
+    matches!(
+        (chars.next(), chars.next(), chars.next()),
+        (Some(letter), Some(':' | '|'), None) if letter.is_ascii_alphabetic()
+    )
+}
+
+/// The drive letter of a path from a Windows library, e.g. C for C:/Users
+pub fn drive_of(path: &Path) -> Option<char> {
+    match path.components().next()? {
+        Component::Normal(first) if is_drive(first.to_str()?) => first.to_str()?.chars().next(),
+        _ => None,
+    }
+}
+
+/// Whether the path is a UNC share path, i.e. //server/share/...
+pub fn is_unc(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("//") && !path.starts_with("///"))
+}
+
+/// Whether the path came from a Windows library, or was rewritten to one.
+pub fn is_windows_path(path: &Path) -> bool {
+    is_unc(path) || drive_of(path).is_some()
+}
+
+/// Strings are kept as the library has them, they're normalized when
+/// they're written out.
+pub fn deserialize_string<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let de = String::deserialize(deserializer)?;
-    // This is one case where this is actually much easier to follow
-    // than the Result <-> Option method-chaining route.
-    match Url::parse(&de.nfc().to_string()) {
-        Ok(url) => Ok(url.to_file_path().ok()),
-        Err(_e) => Ok(None),
+    Ok(String::deserialize(deserializer).ok())
+}
+
+/// Where the tracks of a playlist are resolved from and where the playlist
+/// file itself will end up.
+#[derive(Debug)]
+pub struct LocationContext<'a> {
+    pub roots: &'a [PathBuf],
+    /// The directory entries are made relative to with --relative.
+    pub playlist_dir: &'a Path,
+    /// How many tracks --verify looked for.
+    pub checked: Cell<usize>,
+    /// The resolved locations --verify didn't find.
+    pub missing: RefCell<Vec<PathBuf>>,
+    pub(crate) relocator: Option<&'a Relocator>,
+    /// Counts the tracks each --rewrite rule matched.
+    pub(crate) rewrite_hits: Option<&'a RewriteHits>,
+    /// How many tracks couldn't be resolved or written relative to the
+    /// playlist, they're left out of it.
+    pub unresolved: Cell<usize>,
+}
+
+impl<'a> LocationContext<'a> {
+    pub fn new(roots: &'a [PathBuf], playlist_dir: &'a Path) -> LocationContext<'a> {
+        LocationContext {
+            roots,
+            playlist_dir,
+            checked: Cell::new(0),
+            missing: RefCell::new(Vec::new()),
+            relocator: None,
+            rewrite_hits: None,
+            unresolved: Cell::new(0),
+        }
+    }
+}
+
+/// Resolves the on-disk path of a track, swapping its extension for a
+/// transcoded mirror, normalizing it for the target and relocating it with
+/// --relocate if it isn't there.
+pub fn resolve_path<'a>(
+    ctx: &LocationContext,
+    args: &ExportOptions,
+    track: &'a Track,
+) -> Option<Cow<'a, Path>> {
+    let path = remap_path(ctx, args, track)?;
+    let path = match swap_extension(&args.extension_rules, &path, args.extension_if_exists) {
+        Some(swapped) => Cow::Owned(swapped),
+        None => path,
+    };
+
+    let path = match args.normalization.apply_path(&path) {
+        Cow::Owned(normalized) => Cow::Owned(normalized),
+        Cow::Borrowed(_) => path,
+    };
+
+    match ctx.relocator {
+        Some(relocator) if !path.exists() => relocator
+            .relocate(args, track, &path)
+            .map(Cow::Owned)
+            .or(Some(path)),
+        _ => Some(path),
+    }
+}
+
+/// Remaps the path of a track by the first matching rewrite rule, or
+/// failing that by replacing the library root the track is in with the
+/// music path if one was supplied.
+fn remap_path<'a>(
+    ctx: &LocationContext,
+    args: &ExportOptions,
+    track: &'a Track,
+) -> Option<Cow<'a, Path>> {
+    let name = track.get_name();
+    let loc = track.location.as_ref()?;
+    if let Some((rule, rewritten)) = rewrite_path(&args.rewrite_rules, loc) {
+        if let Some(hits) = ctx.rewrite_hits {
+            hits.record(rule, loc);
+        }
+        return Some(Cow::Owned(rewritten));
+    }
+
+    // If no music path use the original location, the inner
+    // option tracks the success of changing the path so we
+    // are sure to surface the None if the operation fails rather
+    // than .or-ing back in the original location.
+    args.music_path
+        .as_ref()
+        .map(|mpath| {
+            // Roots can be nested, e.g. an external drive mounted inside
+            // the home directory, so go with the most specific.
+            let root = ctx
+                .roots
+                .iter()
+                .filter(|root| loc.starts_with(root))
+                .max_by_key(|root| root.components().count());
+
+            match root.and_then(|root| loc.strip_prefix(root).ok()) {
+                Some(stripped) => Some(Cow::Owned(mpath.join(stripped))),
+                None => {
+                    if args.verbose > 0 {
+                        args.log.warn(&format!("Track {name} is not in any of the library roots"));
+                    }
+                    ctx.unresolved.set(ctx.unresolved.get() + 1);
+                    None
+                }
+            }
+        })
+        .or(Some(Some(Cow::Borrowed(loc.as_path()))))?
+}
+
+/// With --verify, checks that a resolved track exists, keeping track of the
+/// ones that don't. None means the entry should be dropped, otherwise
+/// whether it should be written as a comment.
+pub fn verify_path(ctx: &LocationContext, args: &ExportOptions, path: &Path) -> Option<bool> {
+    if !args.verify {
+        return Some(false);
+    }
+
+    ctx.checked.set(ctx.checked.get() + 1);
+    if path.exists() {
+        return Some(false);
+    }
+
+    ctx.missing.borrow_mut().push(path.to_path_buf());
+    match args.missing {
+        MissingMode::Keep => Some(false),
+        MissingMode::Drop => None,
+        MissingMode::Comment => Some(true),
     }
 }
 
-pub fn deserialize_and_normalize<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+/// Formats a resolved path for a playlist entry, either as a plain path,
+/// a path relative to the playlist's directory or as a file url.
+pub fn format_location<'a>(
+    ctx: &LocationContext,
+    args: &ExportOptions,
+    path: &'a Path,
+) -> Option<Cow<'a, str>> {
+    if args.use_file_url {
+        return to_file_url(args, path).map(Cow::Owned);
+    }
+
+    if args.relative {
+        let relative = relative_to_playlist(ctx, args, path)?;
+        return match args.windows_drive {
+            Some(_) => join_windows_components(&relative).map(Cow::Owned),
+            None => relative.into_os_string().into_string().ok().map(Cow::Owned),
+        };
+    }
+
+    match args.windows_drive {
+        Some(drive) => to_windows_path(path, Some(drive)).map(Cow::Owned),
+        None if is_windows_path(path) => to_windows_path(path, None).map(Cow::Owned),
+        None => Some(Cow::Borrowed(path.to_str()?)),
+    }
+}
+
+/// The path that leads from the base directory to the given path, e.g.
+/// /music/a/b.mp3 from /music/playlists/rock is ../../a/b.mp3. Both paths
+/// need to be absolute and on the same drive.
+pub fn relative_path(path: &Path, base: &Path) -> Option<PathBuf> {
+    let is_rooted = |path: &Path| path.is_absolute() || drive_of(path).is_some();
+    if !is_rooted(path) || !is_rooted(base) || is_unc(path) != is_unc(base) {
+        return None;
+    }
+
+    let path = path.components().collect::<Vec<Component>>();
+    let base = base.components().collect::<Vec<Component>>();
+    if path.first() != base.first() {
+        return None;
+    }
+    let shared = path
+        .iter()
+        .zip(base.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    // Can't walk back up out of a .. without touching the filesystem.
+    if base[shared..]
+        .iter()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+
+    let mut relative = PathBuf::new();
+    for _ in shared..base.len() {
+        relative.push(Component::ParentDir);
+    }
+    relative.extend(&path[shared..]);
+
+    Some(relative)
+}
+
+/// The path relative to the playlist's directory, counting it as
+/// unresolved when there's no way there, e.g. it's on another drive.
+fn relative_to_playlist(ctx: &LocationContext, args: &ExportOptions, path: &Path) -> Option<PathBuf> {
+    let relative = relative_path(path, ctx.playlist_dir);
+    if relative.is_none() {
+        if args.verbose > 0 {
+            args.log.warn(&format!(
+                "Track {} can't be written relative to {}",
+                path.display(),
+                ctx.playlist_dir.display()
+            ));
+        }
+        ctx.unresolved.set(ctx.unresolved.get() + 1);
+    }
+
+    relative
+}
+
+/// Formats a resolved path for the formats whose locations are URIs, as a
+/// relative reference with --relative and a file url otherwise.
+pub fn to_uri(ctx: &LocationContext, args: &ExportOptions, path: &Path) -> Option<String> {
+    if !args.relative {
+        return to_file_url(args, path);
+    }
+
+    let segments = relative_to_playlist(ctx, args, path)?
+        .components()
+        .map(|component| match component {
+            Component::Normal(part) => Some(utf8_percent_encode(part.to_str()?, URI_SEGMENT).to_string()),
+            Component::ParentDir => Some(String::from("..")),
+            _ => None,
+        })
+        .collect::<Option<Vec<String>>>()?;
+
+    Some(segments.join("/"))
+}
+
+/// Converts a resolved path to a file url, honoring the Windows drive
+/// letter if one was supplied.
+pub fn to_file_url<P>(args: &ExportOptions, path: &P) -> Option<String>
 where
-    D: Deserializer<'de>,
+    P: AsRef<Path> + ?Sized,
 {
-    Ok(String::deserialize(deserializer)
-        .ok()
-        .map(|de| de.nfc().to_string()))
+    let path = path.as_ref();
+    if args.windows_drive.is_none() && !is_windows_path(path) {
+        return Url::from_file_path(path).ok().map(String::from);
+    }
+
+    let windows_path = to_windows_path(path, args.windows_drive)?.replace('\\', "/");
+    let url = match windows_path.strip_prefix("//") {
+        Some(unc) => format!("file://{unc}"),
+        None => format!("file:///{windows_path}"),
+    };
+
+    Url::parse(&url).ok().map(String::from)
 }
 
-pub fn write_to_file<P, C>(args: &PlaylisterArgs, path: &P, contents: &C)
+/// Rewrites a path as a Windows path with backslash separators rooted at the
+/// given drive letter, e.g. /Music/foo.m4a -> D:\Music\foo.m4a. Paths from a
+/// Windows library keep their own drive if none is given and UNC paths are
+/// always written as \\server\share\...
+pub fn to_windows_path<P>(path: &P, drive: Option<char>) -> Option<String>
+where
+    P: AsRef<Path> + ?Sized,
+{
+    let path = path.as_ref();
+    if is_unc(path) {
+        return Some(format!("\\\\{}", join_windows_components(path)?));
+    }
+
+    let own_drive = drive_of(path);
+    let drive = drive.or(own_drive)?;
+    let rest = path
+        .components()
+        .skip(own_drive.map_or(0, |_| 1))
+        .collect::<PathBuf>();
+
+    Some(format!("{drive}:\\{}", join_windows_components(&rest)?))
+}
+
+/// Joins the named and .. components of a path with backslashes.
+fn join_windows_components<P>(path: &P) -> Option<String>
+where
+    P: AsRef<Path> + ?Sized,
+{
+    let components = path
+        .as_ref()
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_str()),
+            Component::ParentDir => Some(Some("..")),
+            _ => None,
+        })
+        .collect::<Option<Vec<&str>>>()?;
+
+    Some(components.join("\\"))
+}
+
+/// Looks for album artwork in the same directory as the track.
+pub fn find_artwork<P>(path: &P) -> Option<PathBuf>
+where
+    P: AsRef<Path> + ?Sized,
+{
+    let dir = path.as_ref().parent()?;
+    ARTWORK_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Makes sure the directory for a playlist exists.
+pub fn create_output_dir<P>(args: &ExportOptions, path: &P) -> Result<(), PlaylisterError>
 where
     P: AsRef<Path> + std::fmt::Debug,
-    C: AsRef<[u8]>,
 {
+    if path.as_ref().is_dir() {
+        return Ok(());
+    }
+
     if args.verbose > 1 {
-        println!("Writing file {:?}", path);
+        args.log.info(&format!("Creating directory {:?}", path));
     }
 
-    let res = write(path, contents);
-    if res.is_err() && args.verbose > 0 {
-        eprintln!("Couldn't write file {:?}", path);
+    create_dir_all(path).map_err(|source| PlaylisterError::CreateDir {
+        path: path.as_ref().to_path_buf(),
+        source,
+    })
+}
+
+pub fn write_to_file<P, C>(args: &ExportOptions, path: &P, contents: &C) -> Result<(), PlaylisterError>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    C: AsRef<[u8]>,
+{
+    if args.verbose > 1 {
+        args.log.info(&format!("Writing file {:?}", path));
     }
 
+    write(path, contents).map_err(|source| PlaylisterError::Write {
+        path: path.as_ref().to_path_buf(),
+        source,
+    })?;
+
     if args.verbose > 2 {
-        println!("Done.");
+        args.log.info("Done.");
     }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -93,11 +525,15 @@ mod tests {
     use serde::de::IntoDeserializer;
     use serde::de::value::{StrDeserializer, Error};
     use super::*;
+    use crate::rewrite::RewriteRule;
 
     #[test]
-    fn test_deserialize_and_normalize() {
+    fn test_deserialize_string() {
         let ds: StrDeserializer<Error> = "foobar".into_deserializer();
-        assert_eq!(deserialize_and_normalize(ds), Ok(Some("foobar".nfc().to_string())));
+        assert_eq!(deserialize_string(ds), Ok(Some(String::from("foobar"))));
+
+        let ds: StrDeserializer<Error> = "Cafe\u{301}".into_deserializer();
+        assert_eq!(deserialize_string(ds), Ok(Some(String::from("Cafe\u{301}"))));
     }
 
     #[test]
@@ -107,9 +543,227 @@ mod tests {
     }
 
     #[test]
-    fn test_generate_itunes_prefix() {
-        assert_eq!(generate_itunes_prefix(Path::new("/foo/bar/Music/pizza")).unwrap(), Path::new("/foo/bar/Music"));
-        let err = generate_itunes_prefix(Path::new("/foo/bar")).unwrap_err();
-        assert_eq!(err.to_string(), String::from("Could not determine iTunes prefix from first track location"));
+    fn test_parse_location() {
+        let parse = |location| parse_location(location).map(|path| path.to_str().unwrap().to_string());
+        assert_eq!(parse("file:///Users/me/Caf%C3%A9.mp3").unwrap(), "/Users/me/Café.mp3");
+        assert_eq!(parse("file://localhost/Users/me/a.mp3").unwrap(), "/Users/me/a.mp3");
+        assert_eq!(
+            parse("file://localhost/C:/Users/me/Music/a%20b.mp3").unwrap(),
+            "C:/Users/me/Music/a b.mp3"
+        );
+        assert_eq!(parse("file:///D:/Music/a.mp3").unwrap(), "D:/Music/a.mp3");
+        assert_eq!(parse("file://nas/music/Artist/a.mp3").unwrap(), "//nas/music/Artist/a.mp3");
+        assert_eq!(parse("http://example.com/a.mp3"), None);
+    }
+
+    #[test]
+    fn test_windows_paths() {
+        assert_eq!(portable_path("C:\\Users\\me"), PathBuf::from("C:/Users/me"));
+        assert_eq!(portable_path("\\\\nas\\music"), PathBuf::from("//nas/music"));
+        assert_eq!(portable_path("/Users/me"), PathBuf::from("/Users/me"));
+
+        assert_eq!(drive_of(Path::new("C:/Users/me")), Some('C'));
+        assert_eq!(drive_of(Path::new("/Users/me")), None);
+        assert!(is_unc(Path::new("//nas/music")));
+        assert!(!is_unc(Path::new("/nas/music")));
+
+        let path = Path::new("C:/Users/me/a.mp3");
+        assert_eq!(to_windows_path(path, None).unwrap(), "C:\\Users\\me\\a.mp3");
+        assert_eq!(to_windows_path(path, Some('E')).unwrap(), "E:\\Users\\me\\a.mp3");
+        assert_eq!(to_windows_path(Path::new("/Users/me"), None), None);
+        assert_eq!(to_windows_path(Path::new("//nas/music/a.mp3"), Some('E')).unwrap(), "\\\\nas\\music\\a.mp3");
+    }
+
+    #[test]
+    fn test_format_location_windows_library() {
+        let ctx = LocationContext::new(&[], Path::new("/"));
+        let args = ExportOptions::default();
+        let drive = Path::new("C:/Users/me/a b.mp3");
+        let unc = Path::new("//nas/music/a b.mp3");
+        assert_eq!(format_location(&ctx, &args, drive).unwrap(), "C:\\Users\\me\\a b.mp3");
+        assert_eq!(format_location(&ctx, &args, unc).unwrap(), "\\\\nas\\music\\a b.mp3");
+
+        let args = ExportOptions {
+            use_file_url: true,
+            ..Default::default()
+        };
+        assert_eq!(format_location(&ctx, &args, drive).unwrap(), "file:///C:/Users/me/a%20b.mp3");
+        assert_eq!(format_location(&ctx, &args, unc).unwrap(), "file://nas/music/a%20b.mp3");
+
+        // Remapped onto POSIX paths by a rewrite rule.
+        let args = ExportOptions {
+            rewrite_rules: vec![RewriteRule::parse("C:\\Users\\me => /mnt/me", false).unwrap()],
+            ..Default::default()
+        };
+        let track = Track {
+            location: parse_location("file://localhost/C:/Users/me/a%20b.mp3"),
+            ..Default::default()
+        };
+        let resolved = resolve_path(&ctx, &args, &track).unwrap();
+        assert_eq!(format_location(&ctx, &args, &resolved).unwrap(), "/mnt/me/a b.mp3");
+    }
+
+    #[test]
+    fn test_longest_common_prefix() {
+        let paths = [
+            Path::new("/Volumes/Ext/Music/a/b.mp3"),
+            Path::new("/Volumes/Ext/Music/a/c.mp3"),
+            Path::new("/Volumes/Ext/Musical/d.mp3"),
+        ];
+        assert_eq!(longest_common_prefix(paths), Some(PathBuf::from("/Volumes/Ext")));
+        assert_eq!(longest_common_prefix(paths[..1].iter().copied()), Some(PathBuf::from(paths[0])));
+        assert_eq!(longest_common_prefix(Vec::new()), None);
+
+        let shares = [Path::new("//nas/music/a/b.mp3"), Path::new("//nas/music/c.mp3")];
+        assert_eq!(longest_common_prefix(shares), Some(PathBuf::from("//nas/music")));
+    }
+
+    #[test]
+    fn test_format_location() {
+        let path = Path::new("/foo/bar baz");
+        let ctx = LocationContext::new(&[], Path::new("/"));
+        let args = ExportOptions::default();
+        assert_eq!(format_location(&ctx, &args, path).unwrap(), "/foo/bar baz");
+
+        let args = ExportOptions {
+            use_file_url: true,
+            ..Default::default()
+        };
+        assert_eq!(format_location(&ctx, &args, path).unwrap(), "file:///foo/bar%20baz");
+    }
+
+    #[test]
+    fn test_format_location_windows() {
+        let path = Path::new("/Music/foo bar.m4a");
+        let ctx = LocationContext::new(&[], Path::new("/"));
+        let args = ExportOptions {
+            windows_drive: Some('D'),
+            ..Default::default()
+        };
+        assert_eq!(format_location(&ctx, &args, path).unwrap(), "D:\\Music\\foo bar.m4a");
+
+        let args = ExportOptions {
+            windows_drive: Some('D'),
+            use_file_url: true,
+            ..Default::default()
+        };
+        assert_eq!(format_location(&ctx, &args, path).unwrap(), "file:///D:/Music/foo%20bar.m4a");
+    }
+
+    #[test]
+    fn test_resolve_path_rewrite_rules() {
+        let args = ExportOptions {
+            music_path: Some(PathBuf::from("/baz")),
+            rewrite_rules: vec![RewriteRule::parse("/Volumes/Ext => /mnt/ext", false).unwrap()],
+            ..Default::default()
+        };
+
+        let track = Track {
+            location: Some(PathBuf::from("/Volumes/Ext/foo.mp3")),
+            ..Default::default()
+        };
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let resolved = resolve_path(&ctx, &args, &track).unwrap();
+        assert_eq!(resolved, Path::new("/mnt/ext/foo.mp3"));
+
+        // No rule matches so it falls back to the music path.
+        let track = Track {
+            location: Some(PathBuf::from("/foo/bar.mp3")),
+            ..Default::default()
+        };
+        let resolved = resolve_path(&ctx, &args, &track).unwrap();
+        assert_eq!(resolved, Path::new("/baz/bar.mp3"));
+
+        // Nor does any root, which is recorded for the summary.
+        let track = Track {
+            name: Some(String::from("Stray")),
+            location: Some(PathBuf::from("/elsewhere/bar.mp3")),
+            ..Default::default()
+        };
+        assert!(resolve_path(&ctx, &args, &track).is_none());
+        assert_eq!(ctx.unresolved.get(), 1);
+    }
+
+    #[test]
+    fn test_write_to_file_errors() {
+        let args = ExportOptions::default();
+        let path = Path::new("/no/such/dir/foo.m3u");
+        let err = write_to_file(&args, &path, b"").unwrap_err();
+        assert!(matches!(err, PlaylisterError::Write { .. }));
+        assert_eq!(err.exit_code(), 13);
+    }
+
+    #[test]
+    fn test_verify_path() {
+        let ctx = LocationContext::new(&[], Path::new("/"));
+        let found = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        let missing = Path::new("/no/such/track.mp3");
+        let mut args = ExportOptions::default();
+        assert_eq!(verify_path(&ctx, &args, missing), Some(false));
+        assert_eq!(ctx.checked.get(), 0);
+
+        args.verify = true;
+        assert_eq!(verify_path(&ctx, &args, &found), Some(false));
+        assert_eq!(verify_path(&ctx, &args, missing), Some(false));
+        args.missing = MissingMode::Drop;
+        assert_eq!(verify_path(&ctx, &args, missing), None);
+        args.missing = MissingMode::Comment;
+        assert_eq!(verify_path(&ctx, &args, missing), Some(true));
+
+        assert_eq!(ctx.checked.get(), 4);
+        assert_eq!(ctx.missing.borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let path = Path::new("/music/a/b.mp3");
+        assert_eq!(relative_path(path, Path::new("/music")), Some(PathBuf::from("a/b.mp3")));
+        assert_eq!(
+            relative_path(path, Path::new("/music/playlists/rock")),
+            Some(PathBuf::from("../../a/b.mp3"))
+        );
+        assert_eq!(relative_path(path, Path::new("/other")), Some(PathBuf::from("../music/a/b.mp3")));
+        assert_eq!(relative_path(path, Path::new("playlists")), None);
+        assert_eq!(relative_path(path, Path::new("/music/../x")), None);
+
+        let path = Path::new("C:/Music/a.mp3");
+        assert_eq!(relative_path(path, Path::new("C:/Playlists")), Some(PathBuf::from("../Music/a.mp3")));
+        assert_eq!(relative_path(path, Path::new("D:/Playlists")), None);
+    }
+
+    #[test]
+    fn test_format_location_relative() {
+        let path = Path::new("/sdcard/Music/Artist/foo bar.m4a");
+        let ctx = LocationContext::new(&[], Path::new("/sdcard/Playlists/Genres"));
+        let args = ExportOptions {
+            relative: true,
+            ..Default::default()
+        };
+        assert_eq!(format_location(&ctx, &args, path).unwrap(), "../../Music/Artist/foo bar.m4a");
+
+        let args = ExportOptions {
+            relative: true,
+            windows_drive: Some('E'),
+            ..Default::default()
+        };
+        assert_eq!(
+            format_location(&ctx, &args, path).unwrap(),
+            "..\\..\\Music\\Artist\\foo bar.m4a"
+        );
+    }
+
+    #[test]
+    fn test_format_location_relative_other_drive() {
+        let path = Path::new("C:/Music/a.mp3");
+        let ctx = LocationContext::new(&[], Path::new("D:/Playlists"));
+        let args = ExportOptions {
+            relative: true,
+            ..Default::default()
+        };
+
+        assert_eq!(format_location(&ctx, &args, path), None);
+        assert_eq!(to_uri(&ctx, &args, path), None);
+        assert_eq!(ctx.unresolved.get(), 2);
     }
 }
@@ -0,0 +1,196 @@
+//! playlist_tree
+//!
+//! Rebuilds the iTunes playlist folder hierarchy from the persistent IDs in
+//! the library XML, which stores playlists as a flat list.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::library::Playlist;
+use crate::normalize::Normalization;
+use crate::sanitize::{sanitize_name, FsProfile};
+
+pub struct PlaylistTree<'a> {
+    by_id: HashMap<&'a str, &'a Playlist>,
+}
+
+impl<'a> PlaylistTree<'a> {
+    pub fn new(playlists: &'a [Playlist]) -> PlaylistTree<'a> {
+        let by_id = playlists
+            .iter()
+            .filter_map(|playlist| Some((playlist.persistent_id.as_deref()?, playlist)))
+            .collect();
+
+        PlaylistTree { by_id }
+    }
+
+    pub fn parent(&self, playlist: &Playlist) -> Option<&'a Playlist> {
+        let parent_id = playlist.parent_persistent_id.as_deref()?;
+        self.by_id.get(parent_id).copied()
+    }
+
+    /// The names of the folders containing the playlist, outermost first.
+    pub fn folder_names(&self, playlist: &Playlist) -> Vec<&'a str> {
+        let mut names = Vec::new();
+        let mut current = self.parent(playlist);
+        while let Some(folder) = current {
+            // A malformed library could have a cycle in it, bail rather
+            // than spin forever.
+            if names.len() > self.by_id.len() {
+                break;
+            }
+
+            names.push(folder.name.as_str());
+            current = self.parent(folder);
+        }
+
+        names.reverse();
+        names
+    }
+
+    /// The folder names as a relative path that is normalized and safe for
+    /// the filesystem.
+    pub fn folder_path(
+        &self,
+        playlist: &Playlist,
+        normalization: Normalization,
+        profile: FsProfile,
+    ) -> PathBuf {
+        self.folder_names(playlist)
+            .iter()
+            .map(|name| sanitize_name(profile, &normalization.apply(name)))
+            .collect()
+    }
+
+    /// The playlist name with its folders, e.g. Rock/Classic/Zeppelin.
+    pub fn full_name(&self, playlist: &Playlist) -> String {
+        let mut names = self.folder_names(playlist);
+        names.push(&playlist.name);
+        names.join("/")
+    }
+}
+
+/// Groups the playlists that would be written to the same file name in the
+/// same output folder. Playlists are told apart by their persistent ID so
+/// one listed twice isn't a duplicate of itself.
+pub fn find_duplicates<'a>(
+    normalization: Normalization,
+    profile: FsProfile,
+    playlists: &[(&'a Playlist, PathBuf)],
+) -> Vec<Vec<&'a Playlist>> {
+    let mut groups: Vec<Vec<&Playlist>> = Vec::new();
+    let mut by_name = HashMap::new();
+    for (playlist, folder) in playlists {
+        let name = sanitize_name(profile, &normalization.apply(&playlist.name));
+        let key = (
+            profile.fold(&folder.to_string_lossy()),
+            profile.fold(&name),
+        );
+
+        let index = *by_name.entry(key).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+
+        let group = &mut groups[index];
+
+        let listed = playlist.persistent_id.is_some()
+            && group
+                .iter()
+                .any(|other| other.persistent_id == playlist.persistent_id);
+        if !listed {
+            group.push(playlist);
+        }
+    }
+
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(name: &str, id: &str, parent: Option<&str>, folder: bool) -> Playlist {
+        Playlist {
+            name: String::from(name),
+            persistent_id: Some(String::from(id)),
+            parent_persistent_id: parent.map(String::from),
+            folder,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_folder_path() {
+        let playlists = vec![
+            playlist("Rock", "A", None, true),
+            playlist("Classic", "B", Some("A"), true),
+            playlist("Zeppelin", "C", Some("B"), false),
+            playlist("Loose", "D", None, false),
+        ];
+
+        let tree = PlaylistTree::new(&playlists);
+        assert_eq!(tree.folder_path(&playlists[2], Normalization::Nfc, FsProfile::Ext4), PathBuf::from("Rock/Classic"));
+        assert_eq!(tree.folder_path(&playlists[1], Normalization::Nfc, FsProfile::Ext4), PathBuf::from("Rock"));
+        assert_eq!(tree.folder_path(&playlists[3], Normalization::Nfc, FsProfile::Ext4), PathBuf::new());
+        assert_eq!(tree.parent(&playlists[2]).map(|p| p.name.as_str()), Some("Classic"));
+
+        let playlists = vec![
+            playlist("“Rock”/Pop", "A", None, true),
+            playlist("Hits", "B", Some("A"), false),
+        ];
+        let tree = PlaylistTree::new(&playlists);
+        assert_eq!(
+            tree.folder_path(&playlists[1], Normalization::Nfc, FsProfile::Ext4),
+            PathBuf::from("“Rock”_Pop")
+        );
+        assert_eq!(
+            tree.folder_path(&playlists[1], Normalization::Ascii, FsProfile::Fat),
+            PathBuf::from("_Rock__Pop")
+        );
+    }
+
+    #[test]
+    fn test_folder_path_cycle() {
+        let playlists = vec![
+            playlist("A", "A", Some("B"), true),
+            playlist("B", "B", Some("A"), true),
+        ];
+
+        let tree = PlaylistTree::new(&playlists);
+        assert!(tree.folder_path(&playlists[0], Normalization::Nfc, FsProfile::Ext4).components().count() <= 3);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let playlists = vec![
+            playlist("Rock", "A", None, true),
+            playlist("Pop", "B", None, true),
+            playlist("Mix", "C", Some("A"), false),
+            playlist("mix", "D", Some("B"), false),
+            playlist("Mix", "C", Some("A"), false),
+            playlist("Chill", "E", Some("B"), false),
+        ];
+
+        let tree = PlaylistTree::new(&playlists);
+        assert_eq!(tree.full_name(&playlists[3]), "Pop/mix");
+
+        let flat = playlists[2..].iter().map(|p| (p, PathBuf::new())).collect::<Vec<_>>();
+        assert!(find_duplicates(Normalization::Nfc, FsProfile::Ext4, &flat).is_empty());
+
+        let duplicates = find_duplicates(Normalization::Nfc, FsProfile::Fat, &flat);
+        assert_eq!(duplicates.len(), 1);
+        let ids = duplicates[0].iter().map(|p| p.persistent_id.as_deref()).collect::<Vec<_>>();
+        assert_eq!(ids, [Some("C"), Some("D")]);
+
+        let mirrored = playlists[2..]
+            .iter()
+            .map(|p| (p, tree.folder_path(p, Normalization::Nfc, FsProfile::Fat)))
+            .collect::<Vec<_>>();
+        assert!(find_duplicates(Normalization::Nfc, FsProfile::Fat, &mirrored).is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+//! pls
+//!
+//! Contains functions pertaining to the creation of the PLS playlist format.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use crate::export::ExportOptions;
+use crate::library::Playlist;
+use crate::path_utils::{format_location, resolve_path, verify_path, LocationContext};
+use crate::track::Track;
+use crate::writer::PlaylistWriter;
+
+#[derive(Debug, PartialEq)]
+struct PlsEntry {
+    file: String,
+    title: String,
+    length: i32,
+}
+
+impl PlsEntry {
+    // PLS entries are numbered starting from 1.
+    fn format(&self, number: usize) -> String {
+        format!(
+            "File{number}={}\nTitle{number}={}\nLength{number}={}",
+            self.file, self.title, self.length
+        )
+    }
+}
+
+fn to_pls_track(ctx: &LocationContext, args: &ExportOptions, track: &Track) -> Option<PlsEntry> {
+    let name = args.normalization.apply(track.get_name());
+    if args.verbose > 3 {
+        args.log.info(&format!("Converting track {name}"));
+    }
+
+    // Entries are numbered and there's no comment syntax players agree
+    // on, so missing tracks can only be dropped.
+    let path = resolve_path(ctx, args, track)?;
+    if verify_path(ctx, args, &path)? {
+        return None;
+    }
+
+    let file = format_location(ctx, args, &path)?.into_owned();
+    Some(PlsEntry {
+        file,
+        title: format!(
+            "{} - {}",
+            args.normalization.apply(track.get_artist()),
+            name
+        ),
+        length: track.duration / 1000, // iTunes uses ms but PLS uses seconds
+    })
+}
+
+/// Winamp style PLS. Entries are numbered, so the numbers stay contiguous
+/// when tracks are left out.
+pub struct PlsWriter;
+
+impl PlaylistWriter for PlsWriter {
+    fn name(&self) -> &'static str {
+        "pls"
+    }
+
+    fn extension(&self) -> &str {
+        "pls"
+    }
+
+    fn header(&self, _options: &ExportOptions, _playlist: &Playlist, _entries: &[String]) -> String {
+        String::from("[playlist]\n")
+    }
+
+    fn entry(
+        &self,
+        ctx: &LocationContext,
+        options: &ExportOptions,
+        track: &Track,
+        number: usize,
+    ) -> Option<String> {
+        to_pls_track(ctx, options, track).map(|entry| entry.format(number))
+    }
+
+    fn footer(&self, _options: &ExportOptions, _playlist: &Playlist, entries: &[String]) -> String {
+        // Entries don't end in a newline, the last one needs one.
+        let separator = if entries.is_empty() { "" } else { "\n" };
+        format!("{separator}NumberOfEntries={}\nVersion=2\n", entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use super::*;
+    use crate::library::PlaylistItem;
+
+    #[test]
+    fn test_to_pls_track() {
+        let track = Track {
+            name: Some(String::from("Favorite Song")),
+            location: Some(PathBuf::from("/foo/bar")),
+            duration: 3000,
+            artist: Some(String::from("dude")),
+            ..Default::default()
+        };
+
+        let args = ExportOptions {
+            music_path: Some(PathBuf::from("/baz")),
+            use_file_url: true,
+            ..Default::default()
+        };
+
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let expected = PlsEntry {
+            file: String::from("file:///baz/bar"),
+            title: String::from("dude - Favorite Song"),
+            length: 3,
+        };
+        assert_eq!(to_pls_track(&ctx, &args, &track), Some(expected));
+    }
+
+    #[test]
+    fn test_pls_writer() {
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let playlist = Playlist {
+            name: String::from("foobar"),
+            playlist_items: vec![
+                PlaylistItem { track_id: String::from("1") },
+                PlaylistItem { track_id: String::from("2") },
+                PlaylistItem { track_id: String::from("3") },
+            ],
+            ..Default::default()
+        };
+
+        let tracks = HashMap::from([
+            (String::from("1"), Track {
+                name: Some(String::from("Favorite Song")),
+                location: Some(PathBuf::from("/foo/bar")),
+                duration: 3000,
+                artist: Some(String::from("dude")),
+                ..Default::default()
+            }),
+            (String::from("2"), Track {
+                name: Some(String::from("Least Favorite Song")),
+                location: None,
+                ..Default::default()
+            }),
+            (String::from("3"), Track {
+                name: Some(String::from("Other Song")),
+                location: Some(PathBuf::from("/foo/qux")),
+                duration: 61000,
+                artist: Some(String::from("dude")),
+                ..Default::default()
+            }),
+        ]);
+
+        let args = ExportOptions::default();
+        let expected = String::from(
+            "[playlist]\n\
+            File1=/foo/bar\nTitle1=dude - Favorite Song\nLength1=3\n\
+            File2=/foo/qux\nTitle2=dude - Other Song\nLength2=61\n\
+            NumberOfEntries=2\nVersion=2\n",
+        );
+        let result = (PlsWriter.file_name(&playlist), PlsWriter.render(&ctx, &args, &tracks, &playlist));
+        assert_eq!(result, (String::from("foobar.pls"), expected));
+    }
+
+    #[test]
+    fn test_pls_writer_empty() {
+        let ctx = LocationContext::new(&[], Path::new("/"));
+        let playlist = Playlist {
+            name: String::from("Empty"),
+            ..Default::default()
+        };
+
+        let contents = PlsWriter.render(&ctx, &ExportOptions::default(), &HashMap::new(), &playlist);
+        assert_eq!(contents, "[playlist]\nNumberOfEntries=0\nVersion=2\n");
+    }
+}
@@ -0,0 +1,745 @@
+//! relocate
+//!
+//! Finds tracks that are missing from where their paths were remapped to,
+//! e.g. because the copy of the library was reorganized by beets or Picard.
+//!
+//! The target directory is indexed once and a missing track is matched, in
+//! order, by the reviewed relocation map, by its path ignoring case, by its
+//! file name and finally by how closely the artist, album and title match
+//! the directory and file names, checked against the size and duration of
+//! the best candidates. Only files with a word of the title in their name
+//! are scored that way, scoring every file for every track is too slow on
+//! a big library.
+//!
+//! The relocation map is a tab separated file of library location,
+//! relocated path, confidence and match method. Tabs, newlines and
+//! backslashes in the paths are escaped as `\t`, `\n` and `\\`, any other
+//! backslash is read as is. Matches not confident enough to use are
+//! written commented out for the user to review.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs::{read_dir, read_to_string, write};
+#[cfg(feature = "durations")]
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use strsim::normalized_levenshtein;
+#[cfg(feature = "durations")]
+use symphonia::core::{
+    formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::export::ExportOptions;
+use crate::track::Track;
+
+static AUDIO_EXTENSIONS: [&str; 17] = [
+    "aac", "aif", "aifc", "aiff", "alac", "ape", "flac", "m4a", "m4b", "m4p", "mka", "mp3",
+    "oga", "ogg", "opus", "wav", "wma",
+];
+
+/// Matches below this are only suggested in the relocation map.
+static MIN_CONFIDENCE: f64 = 0.8;
+
+/// How many of the best metadata matches get their duration checked.
+static DURATION_CANDIDATES: usize = 3;
+
+#[derive(Debug)]
+pub struct RelocateError(pub String);
+
+impl Error for RelocateError {}
+
+impl fmt::Display for RelocateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Can't relocate tracks: {}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchMethod {
+    Map,
+    Path,
+    FileName,
+    Metadata,
+}
+
+impl fmt::Display for MatchMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let method = match self {
+            MatchMethod::Map => "map",
+            MatchMethod::Path => "path",
+            MatchMethod::FileName => "filename",
+            MatchMethod::Metadata => "metadata",
+        };
+        write!(f, "{method}")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Relocation {
+    pub path: PathBuf,
+    pub confidence: f64,
+    pub method: MatchMethod,
+}
+
+impl Relocation {
+    pub fn is_confident(&self) -> bool {
+        self.confidence >= MIN_CONFIDENCE
+    }
+}
+
+/// What --relocate did over a run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RelocationReport {
+    /// How many files were found to relocate tracks to.
+    pub indexed: usize,
+    /// How many tracks were looked for.
+    pub missing: usize,
+    pub relocated: usize,
+    /// Matches not confident enough to use, left in the map for review.
+    pub suggested: usize,
+}
+
+#[derive(Debug)]
+struct IndexedFile {
+    path: PathBuf,
+    size: u64,
+    /// Normalized names of the directories between the root and the file.
+    dirs: Vec<String>,
+    title: String,
+}
+
+#[derive(Debug)]
+pub struct Relocator {
+    root: PathBuf,
+    files: Vec<IndexedFile>,
+    by_path: HashMap<String, usize>,
+    by_name: HashMap<String, Vec<usize>>,
+    /// Files by the words of their normalized title.
+    by_word: HashMap<String, Vec<usize>>,
+    map: HashMap<PathBuf, PathBuf>,
+    /// Keyed by library location, None if nothing matched at all.
+    results: RefCell<BTreeMap<PathBuf, Option<Relocation>>>,
+}
+
+impl Relocator {
+    /// Indexes the audio files under the root. Anything below it that can't
+    /// be read is skipped.
+    pub fn new(
+        args: &ExportOptions,
+        root: &Path,
+        map: HashMap<PathBuf, PathBuf>,
+    ) -> Result<Relocator, RelocateError> {
+        let mut paths = Vec::new();
+        collect_audio_files(args, root, &mut paths)
+            .map_err(|err| RelocateError(format!("can't index {}: {err}", root.display())))?;
+
+        let mut relocator = Relocator {
+            root: root.to_path_buf(),
+            files: Vec::with_capacity(paths.len()),
+            by_path: HashMap::new(),
+            by_name: HashMap::new(),
+            by_word: HashMap::new(),
+            map,
+            results: RefCell::new(BTreeMap::new()),
+        };
+
+        paths.sort();
+        for (path, size) in paths {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            let index = relocator.files.len();
+            relocator.by_path.insert(fold_case(relative), index);
+            if let Some(name) = path.file_name() {
+                relocator
+                    .by_name
+                    .entry(fold_case(Path::new(name)))
+                    .or_default()
+                    .push(index);
+            }
+
+            let dirs = relative
+                .parent()
+                .map(|parent| parent.iter().map(|dir| normalize(&dir.to_string_lossy())).collect())
+                .unwrap_or_default();
+            let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+            let title = normalize(strip_track_number(&stem));
+            for word in title.split(' ').filter(|word| !word.is_empty()) {
+                let indices = relocator.by_word.entry(word.to_string()).or_default();
+                if indices.last() != Some(&index) {
+                    indices.push(index);
+                }
+            }
+
+            relocator.files.push(IndexedFile {
+                title,
+                path,
+                size,
+                dirs,
+            });
+        }
+
+        Ok(relocator)
+    }
+
+    pub fn indexed(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Looks for a track that isn't at its resolved path. Only confident
+    /// matches are returned, the rest are kept for the relocation map.
+    pub fn relocate(&self, args: &ExportOptions, track: &Track, path: &Path) -> Option<PathBuf> {
+        let key = track.location.as_ref()?;
+        if let Some(found) = self.results.borrow().get(key) {
+            return found.as_ref().filter(|found| found.is_confident()).map(|found| found.path.clone());
+        }
+
+        let found = self.find(track, path);
+        if args.verbose > 0 {
+            let name = track.get_name();
+            match &found {
+                Some(found) if found.is_confident() => args.log.info(&format!(
+                    "Relocated {name} to {} by {} ({:.2}).",
+                    found.path.display(),
+                    found.method,
+                    found.confidence
+                )),
+                Some(found) => args.log.warn(&format!(
+                    "Not confident enough to relocate {name} to {} ({:.2}).",
+                    found.path.display(),
+                    found.confidence
+                )),
+                None => args.log.warn(&format!("Couldn't relocate {name}.")),
+            }
+        }
+
+        let relocated = found
+            .as_ref()
+            .filter(|found| found.is_confident())
+            .map(|found| found.path.clone());
+        self.results.borrow_mut().insert(key.clone(), found);
+        relocated
+    }
+
+    fn find(&self, track: &Track, path: &Path) -> Option<Relocation> {
+        if let Some(mapped) = track.location.as_ref().and_then(|loc| self.map.get(loc)) {
+            return Some(Relocation {
+                path: mapped.clone(),
+                confidence: 1.0,
+                method: MatchMethod::Map,
+            });
+        }
+
+        let by_path = path
+            .strip_prefix(&self.root)
+            .ok()
+            .and_then(|relative| self.by_path.get(&fold_case(relative)));
+        if let Some(&index) = by_path {
+            return Some(Relocation {
+                path: self.files[index].path.clone(),
+                confidence: 1.0,
+                method: MatchMethod::Path,
+            });
+        }
+
+        let same_name = path
+            .file_name()
+            .and_then(|name| self.by_name.get(&fold_case(Path::new(name))));
+        match same_name.map(Vec::as_slice) {
+            Some([index]) => Some(Relocation {
+                path: self.files[*index].path.clone(),
+                confidence: 0.9,
+                method: MatchMethod::FileName,
+            }),
+            Some(indices) => self.best_match(track, indices.iter().copied()),
+            None => self.best_match(track, self.sharing_title_words(track).into_iter()),
+        }
+    }
+
+    /// The files with at least one word of the track's title in their name.
+    fn sharing_title_words(&self, track: &Track) -> BTreeSet<usize> {
+        normalize(track.get_name())
+            .split(' ')
+            .filter_map(|word| self.by_word.get(word))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    fn best_match<I>(&self, track: &Track, candidates: I) -> Option<Relocation>
+    where
+        I: Iterator<Item = usize>,
+    {
+        let mut scored = candidates
+            .map(|index| (index, self.score(track, &self.files[index])))
+            .filter(|(_, score)| *score > 0.0)
+            .collect::<Vec<(usize, f64)>>();
+        scored.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        scored.truncate(DURATION_CANDIDATES);
+
+        // Only the best few are worth reading the audio headers of.
+        let expected = Duration::from_millis(track.duration.max(0) as u64);
+        scored
+            .into_iter()
+            .map(|(index, score)| {
+                let file = &self.files[index];
+                let score = match probe_duration(&file.path) {
+                    Some(duration) if track.duration > 0 => {
+                        let diff = duration.abs_diff(expected);
+                        if diff <= Duration::from_secs(2) {
+                            (score + 0.1).min(1.0)
+                        } else if diff > Duration::from_secs(10) {
+                            score * 0.5
+                        } else {
+                            score
+                        }
+                    }
+                    _ => score,
+                };
+                (file, score)
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(file, score)| Relocation {
+                path: file.path.clone(),
+                confidence: score,
+                method: MatchMethod::Metadata,
+            })
+    }
+
+    /// How well the directory and file names match the track's artist,
+    /// album and title, between 0 and 1.
+    fn score(&self, track: &Track, file: &IndexedFile) -> f64 {
+        let title = normalize(track.get_name());
+        let title_score = similarity(&title, &file.title);
+        if title_score < 0.5 {
+            return 0.0;
+        }
+
+        let best_dir = |field: &Option<String>| {
+            field.as_ref().map(|value| {
+                let value = normalize(value);
+                file.dirs
+                    .iter()
+                    .map(|dir| similarity(&value, dir))
+                    .fold(0.0, f64::max)
+            })
+        };
+
+        let artist = track.album_artist.as_ref().or(track.artist.as_ref()).cloned();
+        let mut weighted = vec![(title_score, 0.6)];
+        weighted.extend(best_dir(&track.album).map(|score| (score, 0.2)));
+        weighted.extend(best_dir(&artist).map(|score| (score, 0.2)));
+
+        let total = weighted.iter().map(|(_, weight)| weight).sum::<f64>();
+        let score = weighted.iter().map(|(score, weight)| score * weight).sum::<f64>() / total;
+        match track.size {
+            Some(size) if size == file.size => (score + 0.1).min(1.0),
+            _ => score,
+        }
+    }
+
+    /// Writes every relocation, including those from the map that was read
+    /// in, so the file can be reviewed and passed back in on the next run.
+    pub fn write_map<P>(&self, path: &P) -> Result<(), RelocateError>
+    where
+        P: AsRef<Path> + ?Sized,
+    {
+        let mut entries = self
+            .map
+            .iter()
+            .map(|(location, mapped)| {
+                let relocation = Relocation {
+                    path: mapped.clone(),
+                    confidence: 1.0,
+                    method: MatchMethod::Map,
+                };
+                (location.clone(), Some(relocation))
+            })
+            .collect::<BTreeMap<PathBuf, Option<Relocation>>>();
+        entries.extend(self.results.borrow().clone());
+
+        let mut contents = String::from(
+            "# Library location\tRelocated path\tConfidence\tMethod\n\
+            # Commented out entries aren't used, review and uncomment them to use them.\n",
+        );
+        for (location, relocation) in &entries {
+            let line = match relocation {
+                Some(found) => format!(
+                    "{}\t{}\t{:.2}\t{}",
+                    escape_field(location),
+                    escape_field(&found.path),
+                    found.confidence,
+                    found.method
+                ),
+                None => format!("{}\t\t0.00\tunmatched", escape_field(location)),
+            };
+
+            if relocation.as_ref().is_some_and(Relocation::is_confident) {
+                contents.push_str(&line);
+            } else {
+                contents.push_str("# ");
+                contents.push_str(&line);
+            }
+            contents.push('\n');
+        }
+
+        write(path.as_ref(), contents)
+            .map_err(|err| RelocateError(format!("can't write {}: {err}", path.as_ref().display())))
+    }
+
+    /// How many of the missing tracks were relocated.
+    pub fn report(&self) -> RelocationReport {
+        let results = self.results.borrow();
+        let relocated = results
+            .values()
+            .filter(|found| found.as_ref().is_some_and(Relocation::is_confident))
+            .count();
+        let suggested = results.values().filter(|found| found.is_some()).count() - relocated;
+
+        RelocationReport {
+            indexed: self.indexed(),
+            missing: results.len(),
+            relocated,
+            suggested,
+        }
+    }
+}
+
+/// Reads a relocation map written by a previous run. A map that doesn't
+/// exist yet is empty.
+pub fn read_relocation_map<P>(path: &P) -> Result<HashMap<PathBuf, PathBuf>, RelocateError>
+where
+    P: AsRef<Path> + ?Sized,
+{
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let contents = read_to_string(path)
+        .map_err(|err| RelocateError(format!("can't read {}: {err}", path.display())))?;
+
+    let mut map = HashMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split('\t');
+        match (fields.next(), fields.next()) {
+            (Some(location), Some(relocated)) if !location.is_empty() && !relocated.is_empty() => {
+                map.insert(
+                    PathBuf::from(unescape_field(location)),
+                    PathBuf::from(unescape_field(relocated)),
+                );
+            }
+            _ => {
+                return Err(RelocateError(format!(
+                    "line {} of {} needs a location and a relocated path",
+                    number + 1,
+                    path.display()
+                )))
+            }
+        }
+    }
+
+    Ok(map)
+}
+
+/// Escapes a path so it can't break the map's lines or columns.
+fn escape_field(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn unescape_field(field: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('\\') => unescaped.push('\\'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+fn collect_audio_files(
+    args: &ExportOptions,
+    dir: &Path,
+    files: &mut Vec<(PathBuf, u64)>,
+) -> std::io::Result<()> {
+    let skip = |path: &Path, err: std::io::Error| {
+        if args.verbose > 0 {
+            args.log.warn(&format!("Couldn't index {}: {err}", path.display()));
+        }
+    };
+
+    for entry in read_dir(dir)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                skip(dir, err);
+                continue;
+            }
+        };
+
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                skip(&path, err);
+                continue;
+            }
+        };
+
+        if metadata.is_dir() {
+            if let Err(err) = collect_audio_files(args, &path, files) {
+                skip(&path, err);
+            }
+        } else if is_audio_file(&path) {
+            files.push((path, metadata.len()));
+        }
+    }
+
+    Ok(())
+}
+
+fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Case and normalization insensitive key for a path.
+fn fold_case(path: &Path) -> String {
+    path.to_string_lossy().nfc().collect::<String>().to_lowercase()
+}
+
+/// Lower case letters and digits separated by single spaces.
+fn normalize(text: &str) -> String {
+    text.nfc()
+        .collect::<String>()
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Drops the track number taggers put in front of file names, e.g.
+/// 04 Cecilia, 1-04 - Cecilia or 04. Cecilia
+fn strip_track_number(stem: &str) -> &str {
+    let is_digit = |c: char| c.is_ascii_digit();
+    let rest = stem.trim_start_matches(is_digit);
+    if rest.len() == stem.len() {
+        return stem;
+    }
+
+    let rest = match rest.strip_prefix('-') {
+        Some(track) if track.starts_with(is_digit) => track.trim_start_matches(is_digit),
+        _ => rest,
+    };
+
+    match rest.trim_start_matches([' ', '-', '.', '_']) {
+        "" => stem,
+        title => title,
+    }
+}
+
+fn similarity(a: &str, b: &str) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    normalized_levenshtein(a, b)
+}
+
+/// Reads how long a file plays for from its headers, if the format says.
+#[cfg(feature = "durations")]
+fn probe_duration(path: &Path) -> Option<Duration> {
+    let file = File::open(path).ok()?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .ok()?;
+    let params = &probed.format.default_track()?.codec_params;
+    let time = params.time_base?.calc_time(params.n_frames?);
+    Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+}
+
+/// Without the durations feature matches are made on names alone.
+#[cfg(not(feature = "durations"))]
+fn probe_duration(_path: &Path) -> Option<Duration> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all};
+
+    use super::*;
+
+    fn library(name: &str, files: &[&str]) -> PathBuf {
+        let root = temp_dir().join(format!("playlistrs-relocate-{name}-{}", std::process::id()));
+        let _ = remove_dir_all(&root);
+        for file in files {
+            let path = root.join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            write(path, b"not really audio").unwrap();
+        }
+
+        root
+    }
+
+    fn track(location: &str, name: &str, artist: &str, album: &str) -> Track {
+        Track {
+            location: Some(PathBuf::from(location)),
+            name: Some(String::from(name)),
+            artist: Some(String::from(artist)),
+            album: Some(String::from(album)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_strip_track_number() {
+        assert_eq!(strip_track_number("04 Cecilia"), "Cecilia");
+        assert_eq!(strip_track_number("1-04 - Cecilia"), "Cecilia");
+        assert_eq!(strip_track_number("04. Cecilia"), "Cecilia");
+        assert_eq!(strip_track_number("Cecilia"), "Cecilia");
+        assert_eq!(strip_track_number("1999"), "1999");
+    }
+
+    #[test]
+    fn test_relocate() {
+        let root = library(
+            "find",
+            &[
+                "Simon & Garfunkel/Bridge Over Troubled Water/04 Cecilia.M4A",
+                "Zoë/Jazz/01 Café.mp3",
+                "Zoë/Live/Other Café.mp3",
+                "Various/Hits/07 - Take Five.flac",
+                "Various/Hits/cover.jpg",
+            ],
+        );
+
+        let relocator = Relocator::new(&ExportOptions::default(), &root, HashMap::new()).unwrap();
+        assert_eq!(relocator.indexed(), 4);
+
+        let cecilia = track("/lib/a.m4a", "Cecilia", "Simon & Garfunkel", "Bridge Over Troubled Water");
+        let missing = root.join("simon & garfunkel/Bridge Over Troubled Water/04 Cecilia.m4a");
+        let found = relocator.find(&cecilia, &missing).unwrap();
+        assert_eq!(found.method, MatchMethod::Path);
+
+        let cafe = track("/lib/b.mp3", "Café", "Zoë", "Jazz");
+        let found = relocator.find(&cafe, Path::new("/elsewhere/01 Café.mp3")).unwrap();
+        assert_eq!((found.method, found.confidence), (MatchMethod::FileName, 0.9));
+
+        let take_five = track("/lib/c.flac", "Take Five", "Dave Brubeck", "Hits");
+        let found = relocator.find(&take_five, Path::new("/elsewhere/Take Five.flac")).unwrap();
+        assert_eq!(found.method, MatchMethod::Metadata);
+        assert!(found.path.ends_with("07 - Take Five.flac"));
+        assert!(found.is_confident());
+
+        let unknown = track("/lib/d.mp3", "Something Else Entirely", "Nobody", "Nothing");
+        assert_eq!(relocator.find(&unknown, Path::new("/elsewhere/x.mp3")), None);
+
+        remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_relocation_map() {
+        let root = library("map", &["Artist/Album/01 Song.mp3"]);
+        let map_path = root.join("relocations.tsv");
+        assert!(read_relocation_map(&map_path).unwrap().is_empty());
+
+        let map = HashMap::from([(PathBuf::from("/lib/mapped.mp3"), PathBuf::from("/new/mapped.mp3"))]);
+        let relocator = Relocator::new(&ExportOptions::default(), &root, map).unwrap();
+        let args = ExportOptions::default();
+
+        let mapped = track("/lib/mapped.mp3", "Mapped", "Artist", "Album");
+        let relocated = relocator.relocate(&args, &mapped, Path::new("/x/mapped.mp3"));
+        assert_eq!(relocated, Some(PathBuf::from("/new/mapped.mp3")));
+
+        let song = track("/lib/song.mp3", "Song", "Artist", "Album");
+        let relocated = relocator.relocate(&args, &song, Path::new("/x/01 Song.mp3"));
+        assert_eq!(relocated, Some(root.join("Artist/Album/01 Song.mp3")));
+
+        let unknown = track("/lib/unknown.mp3", "Unknown", "Nobody", "Nothing");
+        assert_eq!(relocator.relocate(&args, &unknown, Path::new("/x/u.mp3")), None);
+
+        relocator.write_map(&map_path).unwrap();
+        let contents = read_to_string(&map_path).unwrap();
+        assert!(contents.contains("/lib/mapped.mp3\t/new/mapped.mp3\t1.00\tmap\n"));
+        assert!(contents.contains("# /lib/unknown.mp3\t\t0.00\tunmatched\n"));
+
+        let reread = read_relocation_map(&map_path).unwrap();
+        assert_eq!(reread.len(), 2);
+        assert_eq!(reread[Path::new("/lib/song.mp3")], root.join("Artist/Album/01 Song.mp3"));
+
+        write(&map_path, "/lib/a.mp3\n").unwrap();
+        assert!(read_relocation_map(&map_path).is_err());
+
+        remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_relocation_map_escaping() {
+        let root = library("escape", &[]);
+        create_dir_all(&root).unwrap();
+        let map_path = root.join("relocations.tsv");
+
+        let location = PathBuf::from("/lib/Tab\tNew\nline\\Back.mp3");
+        let relocated = PathBuf::from("/new/Tab\tSong.mp3");
+        let map = HashMap::from([(location.clone(), relocated.clone())]);
+        let relocator = Relocator::new(&ExportOptions::default(), &root, map).unwrap();
+        relocator.write_map(&map_path).unwrap();
+
+        let contents = read_to_string(&map_path).unwrap();
+        assert!(contents.contains("/lib/Tab\\tNew\\nline\\\\Back.mp3\t/new/Tab\\tSong.mp3\t1.00\tmap\n"));
+        assert_eq!(read_relocation_map(&map_path).unwrap(), HashMap::from([(location, relocated)]));
+        assert_eq!(unescape_field("C:\\Music\\a.mp3"), "C:\\Music\\a.mp3");
+
+        remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,319 @@
+//! rewrite
+//!
+//! Ordered path rewrite rules for libraries that span more than one
+//! location, e.g. the internal Music folder, an external drive and a NAS,
+//! and extension rules for transcoded mirrors of a library.
+//!
+//! Rules are written `FROM => TO`. A plain rule replaces the leading path
+//! components matching FROM with TO, a rule prefixed with `regex:` replaces
+//! the match of the FROM pattern and TO can use its capture groups as $1,
+//! ${name} etc. The first matching rule wins. Plain rules can use Windows
+//! paths on either side, e.g. `C:\Users\me\Music => /mnt/music`.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use crate::normalize::Normalization;
+use crate::path_utils::portable_path;
+
+static SEPARATOR: &str = "=>";
+static REGEX_MARKER: &str = "regex:";
+
+#[derive(Debug)]
+pub struct RewriteError(pub String);
+
+impl Error for RewriteError {}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid rewrite rule: {}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub enum RewritePattern {
+    Prefix(PathBuf),
+    Regex(Regex),
+}
+
+#[derive(Debug)]
+pub struct RewriteRule {
+    pub pattern: RewritePattern,
+    pub replacement: String,
+}
+
+impl RewriteRule {
+    pub fn prefix<P>(from: &P, to: &str) -> RewriteRule
+    where
+        P: AsRef<Path> + ?Sized,
+    {
+        RewriteRule {
+            pattern: RewritePattern::Prefix(from.as_ref().to_path_buf()),
+            replacement: String::from(to),
+        }
+    }
+
+    pub fn regex(from: &str, to: &str) -> Result<RewriteRule, RewriteError> {
+        let regex = Regex::new(from).map_err(|err| RewriteError(err.to_string()))?;
+        Ok(RewriteRule {
+            pattern: RewritePattern::Regex(regex),
+            replacement: String::from(to),
+        })
+    }
+
+    /// Parses `FROM => TO`, or `regex:FROM => TO` when `regex` is false.
+    pub fn parse(spec: &str, regex: bool) -> Result<RewriteRule, RewriteError> {
+        let (from, to) = spec
+            .split_once(SEPARATOR)
+            .ok_or_else(|| RewriteError(format!("'{spec}' is missing '{SEPARATOR}'")))?;
+
+        let (from, to) = (from.trim(), to.trim());
+        if from.is_empty() || to.is_empty() {
+            return Err(RewriteError(format!("'{spec}' needs both sides of the rule")));
+        }
+
+        match from.strip_prefix(REGEX_MARKER) {
+            Some(pattern) => RewriteRule::regex(pattern.trim(), to),
+            None if regex => RewriteRule::regex(from, to),
+            None => Ok(RewriteRule::prefix(&portable_path(from), &portable_path(to).to_string_lossy())),
+        }
+    }
+
+    pub fn apply(&self, path: &Path) -> Option<PathBuf> {
+        let rewritten = match &self.pattern {
+            RewritePattern::Prefix(prefix) => {
+                let stripped = match path.strip_prefix(prefix) {
+                    Ok(stripped) => stripped.to_path_buf(),
+                    // The rule and the library can spell accents differently.
+                    Err(_) => {
+                        let nfc = Normalization::Nfc;
+                        if !nfc.apply_path(path).starts_with(nfc.apply_path(prefix)) {
+                            return None;
+                        }
+                        path.components().skip(prefix.components().count()).collect()
+                    }
+                };
+                Path::new(&self.replacement).join(stripped)
+            }
+            RewritePattern::Regex(regex) => {
+                let original = Normalization::Nfc.apply(path.to_str()?);
+                if !regex.is_match(&original) {
+                    return None;
+                }
+                PathBuf::from(regex.replace(&original, self.replacement.as_str()).as_ref())
+            }
+        };
+
+        Some(rewritten)
+    }
+}
+
+impl fmt::Display for RewriteRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.pattern {
+            RewritePattern::Prefix(prefix) => {
+                write!(f, "{} {SEPARATOR} {}", prefix.display(), self.replacement)
+            }
+            RewritePattern::Regex(regex) => {
+                write!(f, "{REGEX_MARKER}{} {SEPARATOR} {}", regex, self.replacement)
+            }
+        }
+    }
+}
+
+/// Swaps one file extension for another, e.g. m4a=opus for a transcoded
+/// mirror of the library.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ExtensionRule {
+    pub from: String,
+    pub to: String,
+}
+
+impl ExtensionRule {
+    /// Parses `FROM=TO`, with or without the leading dots.
+    pub fn parse(spec: &str) -> Result<ExtensionRule, RewriteError> {
+        let (from, to) = spec
+            .split_once('=')
+            .ok_or_else(|| RewriteError(format!("'{spec}' is missing '='")))?;
+
+        let (from, to) = (from.trim().trim_start_matches('.'), to.trim().trim_start_matches('.'));
+        if from.is_empty() || to.is_empty() || from.contains('/') || to.contains('/') {
+            return Err(RewriteError(format!("'{spec}' needs two extensions like m4a=opus")));
+        }
+
+        Ok(ExtensionRule {
+            from: from.to_lowercase(),
+            to: String::from(to),
+        })
+    }
+
+    pub fn apply(&self, path: &Path) -> Option<PathBuf> {
+        let ext = path.extension()?.to_str()?;
+        if ext.to_lowercase() == self.from {
+            Some(path.with_extension(&self.to))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for ExtensionRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.from, self.to)
+    }
+}
+
+/// Swaps the extension by the first matching rule, if any. With
+/// `if_exists` the swap only happens when the new file is there.
+pub fn swap_extension(rules: &[ExtensionRule], path: &Path, if_exists: bool) -> Option<PathBuf> {
+    rules
+        .iter()
+        .find_map(|rule| rule.apply(path))
+        .filter(|swapped| !if_exists || swapped.exists())
+}
+
+/// Reads rules from a file, one per line. Blank lines and lines starting
+/// with # are ignored.
+pub fn read_rewrite_file<P>(path: &P) -> Result<Vec<RewriteRule>, RewriteError>
+where
+    P: AsRef<Path> + ?Sized,
+{
+    let contents = read_to_string(path.as_ref()).map_err(|err| {
+        RewriteError(format!("can't read {}: {err}", path.as_ref().display()))
+    })?;
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| RewriteRule::parse(line, false))
+        .collect()
+}
+
+/// Applies the first matching rule, if any, returning its index along with
+/// the rewritten path.
+pub fn rewrite_path(rules: &[RewriteRule], path: &Path) -> Option<(usize, PathBuf)> {
+    rules
+        .iter()
+        .enumerate()
+        .find_map(|(index, rule)| Some((index, rule.apply(path)?)))
+}
+
+/// The distinct track locations each rule matched over a run. A track in
+/// more than one playlist is only counted once.
+#[derive(Debug, Default)]
+pub struct RewriteHits(RefCell<HashMap<usize, HashSet<PathBuf>>>);
+
+impl RewriteHits {
+    pub fn record(&self, rule: usize, location: &Path) {
+        self.0
+            .borrow_mut()
+            .entry(rule)
+            .or_default()
+            .insert(location.to_path_buf());
+    }
+
+    pub fn count(&self, rule: usize) -> usize {
+        self.0.borrow().get(&rule).map_or(0, HashSet::len)
+    }
+}
+
+/// Each rule, as it was given, with how many tracks it matched.
+pub fn rewrite_hit_counts(rules: &[RewriteRule], hits: &RewriteHits) -> Vec<(String, usize)> {
+    rules
+        .iter()
+        .enumerate()
+        .map(|(index, rule)| (rule.to_string(), hits.count(index)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let rule = RewriteRule::parse("/Users/me/Music => /mnt/music", false).unwrap();
+        assert_eq!(rule.to_string(), "/Users/me/Music => /mnt/music");
+
+        let rule = RewriteRule::parse(r"regex:^/Volumes/(\w+)/ => /mnt/$1/", false).unwrap();
+        assert!(matches!(rule.pattern, RewritePattern::Regex(_)));
+
+        let rule = RewriteRule::parse(r"^/Volumes/(\w+)/ => /mnt/$1/", true).unwrap();
+        assert!(matches!(rule.pattern, RewritePattern::Regex(_)));
+
+        let err = RewriteRule::parse("/Users/me/Music", false).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid rewrite rule: '/Users/me/Music' is missing '=>'");
+        assert!(RewriteRule::parse("regex:( => /mnt", false).is_err());
+        assert!(RewriteRule::parse(" => /mnt", false).is_err());
+
+        let rule = RewriteRule::parse("C:\\Users\\me => \\\\nas\\music", false).unwrap();
+        assert_eq!(rule.to_string(), "C:/Users/me => //nas/music");
+    }
+
+    #[test]
+    fn test_rewrite_hits() {
+        let hits = RewriteHits::default();
+        hits.record(0, Path::new("/a.mp3"));
+        hits.record(0, Path::new("/a.mp3"));
+        hits.record(0, Path::new("/b.mp3"));
+        hits.record(2, Path::new("/a.mp3"));
+        assert_eq!((hits.count(0), hits.count(1), hits.count(2)), (2, 0, 1));
+    }
+
+    #[test]
+    fn test_rewrite_path_in_order() {
+        let rules = vec![
+            RewriteRule::parse("/Users/me/Music => /mnt/music", false).unwrap(),
+            RewriteRule::parse(r"regex:^/Volumes/(\w+)/Music/ => /mnt/$1/", false).unwrap(),
+            RewriteRule::parse("/Volumes => /never", false).unwrap(),
+        ];
+
+        let rewritten = rewrite_path(&rules, Path::new("/Users/me/Music/a/b.m4a"));
+        assert_eq!(rewritten, Some((0, PathBuf::from("/mnt/music/a/b.m4a"))));
+
+        let rewritten = rewrite_path(&rules, Path::new("/Volumes/Ext/Music/c.mp3"));
+        assert_eq!(rewritten, Some((1, PathBuf::from("/mnt/Ext/c.mp3"))));
+
+        // Prefix rules match whole components only.
+        assert_eq!(rewrite_path(&rules, Path::new("/Users/me/Musical/d.mp3")), None);
+
+        // Accents match whichever way they're encoded, the rest of the path
+        // is kept as it was.
+        let rule = RewriteRule::parse("/Music/Zo\u{eb} => /mnt/zoe", false).unwrap();
+        let rewritten = rule.apply(Path::new("/Music/Zoe\u{308}/Cafe\u{301}.mp3"));
+        assert_eq!(rewritten, Some(PathBuf::from("/mnt/zoe/Cafe\u{301}.mp3")));
+    }
+
+    #[test]
+    fn test_swap_extension() {
+        let rules = vec![
+            ExtensionRule::parse("m4a=opus").unwrap(),
+            ExtensionRule::parse(".AIFF = .mp3").unwrap(),
+        ];
+        assert_eq!(rules[1].to_string(), "aiff=mp3");
+        assert!(ExtensionRule::parse("m4a").is_err());
+        assert!(ExtensionRule::parse("m4a=").is_err());
+
+        let swapped = swap_extension(&rules, Path::new("/mnt/music/a.b/c.M4A"), false);
+        assert_eq!(swapped, Some(PathBuf::from("/mnt/music/a.b/c.opus")));
+        let swapped = swap_extension(&rules, Path::new("/mnt/music/c.aiff"), false);
+        assert_eq!(swapped, Some(PathBuf::from("/mnt/music/c.mp3")));
+        assert_eq!(swap_extension(&rules, Path::new("/mnt/music/c.flac"), false), None);
+
+        // Only if the transcoded file is actually there.
+        assert_eq!(swap_extension(&rules, Path::new("/no/such/c.m4a"), true), None);
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.m4a");
+        let swapped = swap_extension(&[ExtensionRule::parse("m4a=toml").unwrap()], &manifest, true);
+        assert_eq!(swapped, Some(manifest.with_extension("toml")));
+    }
+}
@@ -0,0 +1,235 @@
+//! sanitize
+//!
+//! Makes playlist and folder names safe to use as file names on the
+//! filesystem the playlists are going to end up on, e.g. a FAT32 formatted
+//! SD card or a phone.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Both FAT and NTFS limit names to 255 UTF-16 code units, ext4 and the
+/// Android storage layer to 255 bytes.
+static MAX_NAME_LEN: usize = 255;
+
+static REPLACEMENT: char = '_';
+
+/// DOS device names Windows won't create files for, with any extension.
+static RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FsProfile {
+    #[default]
+    Ext4,
+    /// FAT32 and exFAT, e.g. SD cards and USB sticks.
+    Fat,
+    Ntfs,
+    Android,
+}
+
+impl FsProfile {
+    pub fn from_arg(arg: &str) -> Option<FsProfile> {
+        match arg {
+            "ext4" => Some(FsProfile::Ext4),
+            "fat32" | "exfat" => Some(FsProfile::Fat),
+            "ntfs" => Some(FsProfile::Ntfs),
+            "android" => Some(FsProfile::Android),
+            _ => None,
+        }
+    }
+
+    fn is_forbidden(self, c: char) -> bool {
+        match self {
+            FsProfile::Ext4 => c == '/' || c.is_control(),
+            FsProfile::Fat | FsProfile::Ntfs | FsProfile::Android => {
+                c.is_control() || matches!(c, '"' | '*' | '/' | ':' | '<' | '>' | '?' | '\\' | '|')
+            }
+        }
+    }
+
+    fn len(self, name: &str) -> usize {
+        match self {
+            FsProfile::Ext4 | FsProfile::Android => name.len(),
+            FsProfile::Fat | FsProfile::Ntfs => name.encode_utf16().count(),
+        }
+    }
+
+    /// Whether names that only differ in case are the same file.
+    fn ignores_case(self) -> bool {
+        self != FsProfile::Ext4
+    }
+
+    /// The name as the filesystem compares it.
+    pub fn fold(self, name: &str) -> String {
+        if self.ignores_case() {
+            name.to_lowercase()
+        } else {
+            String::from(name)
+        }
+    }
+
+    /// Whether trailing dots and spaces are silently dropped.
+    fn trims_end(self) -> bool {
+        self != FsProfile::Ext4
+    }
+
+    fn has_reserved_names(self) -> bool {
+        matches!(self, FsProfile::Fat | FsProfile::Ntfs)
+    }
+}
+
+/// Makes a folder name safe for the filesystem.
+pub fn sanitize_name(profile: FsProfile, name: &str) -> String {
+    sanitize(profile, name, None, "")
+}
+
+/// Makes a playlist file name safe for the filesystem, keeping its
+/// extension intact.
+pub fn sanitize_file_name(profile: FsProfile, file_name: &str) -> String {
+    let (stem, ext) = split_extension(file_name);
+    sanitize(profile, stem, ext, "")
+}
+
+fn split_extension(file_name: &str) -> (&str, Option<&str>) {
+    match file_name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, Some(ext)),
+        _ => (file_name, None),
+    }
+}
+
+fn sanitize(profile: FsProfile, stem: &str, ext: Option<&str>, suffix: &str) -> String {
+    let mut stem = stem
+        .chars()
+        .map(|c| if profile.is_forbidden(c) { REPLACEMENT } else { c })
+        .collect::<String>();
+
+    let reserved = stem.split('.').next().unwrap_or_default().to_uppercase();
+    if profile.has_reserved_names() && RESERVED_NAMES.contains(&reserved.trim_end()) {
+        stem.insert(0, REPLACEMENT);
+    }
+
+    // Leave room for the suffix and extension, cutting on a char boundary.
+    let tail = match ext {
+        Some(ext) => format!("{suffix}.{ext}"),
+        None => String::from(suffix),
+    };
+    while !stem.is_empty() && profile.len(&stem) + profile.len(&tail) > MAX_NAME_LEN {
+        stem.pop();
+    }
+
+    if profile.trims_end() {
+        stem.truncate(stem.trim_end_matches(['.', ' ']).len());
+    }
+
+    if stem.is_empty() || (ext.is_none() && suffix.is_empty() && (stem == "." || stem == "..")) {
+        stem = String::from(REPLACEMENT);
+    }
+
+    stem + &tail
+}
+
+/// Hands out file names that are safe for the filesystem and unique within
+/// their directory.
+#[derive(Debug)]
+pub struct FileNamer {
+    profile: FsProfile,
+    used: HashMap<PathBuf, HashSet<String>>,
+}
+
+impl FileNamer {
+    pub fn new(profile: FsProfile) -> FileNamer {
+        FileNamer {
+            profile,
+            used: HashMap::new(),
+        }
+    }
+
+    /// Sanitizes the file name and numbers it, e.g. Mix (2).m3u, if another
+    /// playlist in the same directory already has that name.
+    pub fn claim(&mut self, dir: &Path, file_name: &str) -> String {
+        let dir = PathBuf::from(self.profile.fold(&dir.to_string_lossy()));
+        let (stem, ext) = split_extension(file_name);
+        let mut name = sanitize_file_name(self.profile, file_name);
+        let mut number = 1;
+        while self.used.get(&dir).is_some_and(|used| used.contains(&self.profile.fold(&name))) {
+            number += 1;
+            name = sanitize(self.profile, stem, ext, &format!(" ({number})"));
+        }
+
+        let folded = self.profile.fold(&name);
+        self.used.entry(dir).or_default().insert(folded);
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_file_name() {
+        let name = "AC/DC: Best of? <Live> | \"1979\".m3u";
+        assert_eq!(sanitize_file_name(FsProfile::Ext4, name), "AC_DC: Best of? <Live> | \"1979\".m3u");
+        assert_eq!(sanitize_file_name(FsProfile::Fat, name), "AC_DC_ Best of_ _Live_ _ _1979_.m3u");
+        assert_eq!(sanitize_file_name(FsProfile::Android, "a\\b*.m3u8"), "a_b_.m3u8");
+
+        assert_eq!(sanitize_file_name(FsProfile::Ntfs, "con.m3u"), "_con.m3u");
+        assert_eq!(sanitize_file_name(FsProfile::Ntfs, "LPT1.backup.m3u"), "_LPT1.backup.m3u");
+        assert_eq!(sanitize_file_name(FsProfile::Ntfs, "Console.m3u"), "Console.m3u");
+        assert_eq!(sanitize_file_name(FsProfile::Android, "con.m3u"), "con.m3u");
+
+        assert_eq!(sanitize_file_name(FsProfile::Fat, "Mix. .m3u"), "Mix.m3u");
+        assert_eq!(sanitize_file_name(FsProfile::Ext4, "Mix. .m3u"), "Mix. .m3u");
+        assert_eq!(sanitize_file_name(FsProfile::Fat, "....m3u"), "_.m3u");
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name(FsProfile::Ext4, "Rock/Pop"), "Rock_Pop");
+        assert_eq!(sanitize_name(FsProfile::Ext4, ".."), "_");
+        assert_eq!(sanitize_name(FsProfile::Ntfs, "Etc..."), "Etc");
+        assert_eq!(sanitize_name(FsProfile::Ntfs, "AUX"), "_AUX");
+    }
+
+    #[test]
+    fn test_length_limits() {
+        // 200 two byte characters fit NTFS but not ext4.
+        let long = format!("{}.m3u", "é".repeat(200));
+        assert_eq!(sanitize_file_name(FsProfile::Ntfs, &long), long);
+
+        let ext4 = sanitize_file_name(FsProfile::Ext4, &long);
+        assert!(ext4.len() <= MAX_NAME_LEN);
+        assert!(ext4.ends_with("é.m3u"));
+
+        let emoji = format!("{}.m3u", "🎵".repeat(200));
+        let fat = sanitize_file_name(FsProfile::Fat, &emoji);
+        assert!(fat.encode_utf16().count() <= MAX_NAME_LEN);
+        assert!(fat.ends_with("🎵.m3u"));
+    }
+
+    #[test]
+    fn test_file_namer_collisions() {
+        let mut namer = FileNamer::new(FsProfile::Fat);
+        let dir = Path::new("/out");
+        assert_eq!(namer.claim(dir, "Mix?.m3u"), "Mix_.m3u");
+        assert_eq!(namer.claim(dir, "Mix*.m3u"), "Mix_ (2).m3u");
+        assert_eq!(namer.claim(dir, "mix:.m3u"), "mix_ (3).m3u");
+        assert_eq!(namer.claim(Path::new("/out/Rock"), "Mix?.m3u"), "Mix_.m3u");
+
+        let mut namer = FileNamer::new(FsProfile::Ext4);
+        assert_eq!(namer.claim(dir, "Mix.m3u"), "Mix.m3u");
+        assert_eq!(namer.claim(dir, "mix.m3u"), "mix.m3u");
+
+        let long = format!("{}.m3u", "a".repeat(300));
+        let first = namer.claim(dir, &long);
+        let second = namer.claim(dir, &long);
+        assert_eq!(first.len(), MAX_NAME_LEN);
+        assert_eq!(second.len(), MAX_NAME_LEN);
+        assert!(second.ends_with("a (2).m3u"));
+    }
+}
@@ -0,0 +1,673 @@
+//! smart
+//!
+//! Decodes the binary `Smart Info` and `Smart Criteria` blobs iTunes stores
+//! for smart playlists into a typed rule tree.
+//!
+//! Apple has never documented the format, the layout here is the one the
+//! various open source importers agree on. All integers are big endian.
+//!
+//! Smart Info:
+//!
+//! ```text
+//! 0       live updating (1 = on)
+//! 1       rules enabled (1 = on)
+//! 2       limit enabled (1 = on)
+//! 3       limit unit (1 minutes, 2 MB, 3 items, 4 hours, 5 GB)
+//! 7       limit selection, see `Selection`
+//! 8..12   limit value (u32)
+//! 12      match only checked items (1 = on)
+//! 13      reverse the limit selection (1 = on), e.g. lowest rating
+//! ```
+//!
+//! Smart Criteria is a 136 byte header followed by the rules:
+//!
+//! ```text
+//! 0..4    "SLst"
+//! 8..12   rule count (u32)
+//! 12..16  conjunction (u32, 0 = match all, 1 = match any)
+//! ```
+//!
+//! Each rule is:
+//!
+//! ```text
+//! 0..4    field (u32), see `Field`
+//! 4       sign, bit 0 set for string comparisons, bit 1 set for negation
+//! 5..8    operator (u24), see `Operator`
+//! 52..56  data length (u32)
+//! 56..    data
+//! ```
+//!
+//! For string fields the data is the UTF-16BE value. For everything else
+//! it's a 68 byte block with the first value (i64) at 0, the "in the last"
+//! count (i64, negative) at 8, the "in the last" unit in seconds (i64) at 16
+//! and the second value of a range (i64) at 24. Dates are in seconds since
+//! the classic Mac epoch (1904-01-01).
+//!
+//! A rule on field 0 is a nested group of rules, its data is a whole Smart
+//! Criteria block with its own conjunction, e.g. (genre is Rock or genre is
+//! Metal) and rating > 3 stars.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::de::{Deserializer, Visitor};
+
+static CRITERIA_MAGIC: &[u8; 4] = b"SLst";
+static CRITERIA_HEADER_LENGTH: usize = 136;
+static RULE_HEADER_LENGTH: usize = 56;
+static INT_DATA_LENGTH: usize = 68;
+static MAC_EPOCH_OFFSET: u64 = 2_082_844_800; // seconds from 1904 to 1970
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SmartDecodeError(pub String);
+
+impl Error for SmartDecodeError {}
+
+impl fmt::Display for SmartDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid smart playlist data: {}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitUnit {
+    Items,
+    Minutes,
+    Hours,
+    Megabytes,
+    Gigabytes,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Selection {
+    Random,
+    Name,
+    Album,
+    Artist,
+    Genre,
+    HighestRating,
+    MostRecentlyPlayed,
+    MostOftenPlayed,
+    MostRecentlyAdded,
+    Unknown(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limit {
+    pub value: u32,
+    pub unit: LimitUnit,
+    pub selection: Selection,
+    // e.g. lowest rating instead of highest.
+    pub reverse: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Conjunction {
+    All,
+    Any,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldType {
+    Text,
+    Integer,
+    Date,
+    Boolean,
+    Playlist,
+    Group,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Name,
+    Album,
+    Artist,
+    AlbumArtist,
+    Composer,
+    Genre,
+    Kind,
+    Comments,
+    Grouping,
+    SortName,
+    SortAlbum,
+    SortArtist,
+    SortAlbumArtist,
+    SortComposer,
+    BitRate,
+    SampleRate,
+    Year,
+    TrackNumber,
+    DiscNumber,
+    Size,
+    Time,
+    PlayCount,
+    SkipCount,
+    Rating,
+    Bpm,
+    DateAdded,
+    DateModified,
+    LastPlayed,
+    LastSkipped,
+    Compilation,
+    Loved,
+    Playlist,
+    /// A nested group of rules.
+    Group,
+    Unknown(u32),
+}
+
+impl Field {
+    fn from_id(id: u32) -> Field {
+        match id {
+            0x00 => Field::Group,
+            0x02 => Field::Name,
+            0x03 => Field::Album,
+            0x04 => Field::Artist,
+            0x05 => Field::BitRate,
+            0x06 => Field::SampleRate,
+            0x07 => Field::Year,
+            0x08 => Field::Genre,
+            0x09 => Field::Kind,
+            0x0a => Field::DateModified,
+            0x0b => Field::TrackNumber,
+            0x0c => Field::Size,
+            0x0d => Field::Time,
+            0x0e => Field::Comments,
+            0x10 => Field::DateAdded,
+            0x12 => Field::Composer,
+            0x16 => Field::PlayCount,
+            0x17 => Field::LastPlayed,
+            0x18 => Field::DiscNumber,
+            0x19 => Field::Rating,
+            0x1f => Field::Compilation,
+            0x23 => Field::Bpm,
+            0x27 => Field::Grouping,
+            0x28 => Field::Playlist,
+            0x44 => Field::SkipCount,
+            0x45 => Field::LastSkipped,
+            0x47 => Field::AlbumArtist,
+            0x4e => Field::SortName,
+            0x4f => Field::SortAlbum,
+            0x50 => Field::SortArtist,
+            0x51 => Field::SortAlbumArtist,
+            0x52 => Field::SortComposer,
+            0x9a => Field::Loved,
+            _ => Field::Unknown(id),
+        }
+    }
+
+    pub fn field_type(&self) -> Option<FieldType> {
+        match self {
+            Field::Name
+            | Field::Album
+            | Field::Artist
+            | Field::AlbumArtist
+            | Field::Composer
+            | Field::Genre
+            | Field::Kind
+            | Field::Comments
+            | Field::Grouping
+            | Field::SortName
+            | Field::SortAlbum
+            | Field::SortArtist
+            | Field::SortAlbumArtist
+            | Field::SortComposer => Some(FieldType::Text),
+            Field::BitRate
+            | Field::SampleRate
+            | Field::Year
+            | Field::TrackNumber
+            | Field::DiscNumber
+            | Field::Size
+            | Field::Time
+            | Field::PlayCount
+            | Field::SkipCount
+            | Field::Rating
+            | Field::Bpm => Some(FieldType::Integer),
+            Field::DateAdded | Field::DateModified | Field::LastPlayed | Field::LastSkipped => {
+                Some(FieldType::Date)
+            }
+            Field::Compilation | Field::Loved => Some(FieldType::Boolean),
+            Field::Playlist => Some(FieldType::Playlist),
+            Field::Group => Some(FieldType::Group),
+            Field::Unknown(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    Is,
+    Contains,
+    StartsWith,
+    EndsWith,
+    GreaterThan,
+    LessThan,
+    InRange,
+    InTheLast,
+    Unknown(u32),
+}
+
+impl Operator {
+    fn from_id(id: u32) -> Operator {
+        match id {
+            0x001 => Operator::Is,
+            0x002 => Operator::Contains,
+            0x004 => Operator::StartsWith,
+            0x008 => Operator::EndsWith,
+            0x010 => Operator::GreaterThan,
+            0x040 => Operator::LessThan,
+            0x100 => Operator::InRange,
+            0x200 => Operator::InTheLast,
+            _ => Operator::Unknown(id),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuleValue {
+    Text(String),
+    Integer(i64, i64),
+    Date(SystemTime, SystemTime),
+    // Relative to when the playlist is evaluated.
+    Within(Duration),
+    // Playlist rules store the persistent ID of the other playlist.
+    Playlist(String),
+    Group(SmartCriteria),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub field: Field,
+    pub operator: Operator,
+    pub negated: bool,
+    pub value: RuleValue,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SmartCriteria {
+    pub conjunction: Conjunction,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SmartPlaylist {
+    pub live_updating: bool,
+    pub match_checked_only: bool,
+    pub limit: Option<Limit>,
+    // None when the rules checkbox is off and only the limit applies.
+    pub criteria: Option<SmartCriteria>,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, SmartDecodeError> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| SmartDecodeError(format!("truncated at byte {offset}")))
+}
+
+fn read_i64(data: &[u8], offset: usize) -> Result<i64, SmartDecodeError> {
+    data.get(offset..offset + 8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(i64::from_be_bytes)
+        .ok_or_else(|| SmartDecodeError(format!("truncated at byte {offset}")))
+}
+
+fn read_byte(data: &[u8], offset: usize) -> Result<u8, SmartDecodeError> {
+    data.get(offset)
+        .copied()
+        .ok_or_else(|| SmartDecodeError(format!("truncated at byte {offset}")))
+}
+
+pub fn mac_time(seconds: i64) -> Result<SystemTime, SmartDecodeError> {
+    let out_of_range = || SmartDecodeError(format!("date {seconds} is out of range"));
+    let unix = seconds
+        .checked_sub(MAC_EPOCH_OFFSET as i64)
+        .ok_or_else(out_of_range)?;
+    let time = if unix >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs(unix as u64))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(unix.unsigned_abs()))
+    };
+
+    time.ok_or_else(out_of_range)
+}
+
+fn decode_limit(info: &[u8]) -> Result<Option<Limit>, SmartDecodeError> {
+    if read_byte(info, 2)? != 1 {
+        return Ok(None);
+    }
+
+    let unit = match read_byte(info, 3)? {
+        1 => LimitUnit::Minutes,
+        2 => LimitUnit::Megabytes,
+        3 => LimitUnit::Items,
+        4 => LimitUnit::Hours,
+        5 => LimitUnit::Gigabytes,
+        other => return Err(SmartDecodeError(format!("unknown limit unit {other}"))),
+    };
+
+    let selection = match read_byte(info, 7)? {
+        0x02 => Selection::Random,
+        0x03 => Selection::Name,
+        0x04 => Selection::Album,
+        0x05 => Selection::Artist,
+        0x07 => Selection::Genre,
+        0x10 => Selection::MostRecentlyAdded,
+        0x14 => Selection::MostOftenPlayed,
+        0x15 => Selection::MostRecentlyPlayed,
+        0x1c => Selection::HighestRating,
+        other => Selection::Unknown(other),
+    };
+
+    Ok(Some(Limit {
+        value: read_u32(info, 8)?,
+        unit,
+        selection,
+        reverse: read_byte(info, 13)? == 1,
+    }))
+}
+
+fn decode_rule(data: &[u8], offset: usize) -> Result<(Rule, usize), SmartDecodeError> {
+    let field = Field::from_id(read_u32(data, offset)?);
+    let sign = read_byte(data, offset + 4)?;
+    let operator = Operator::from_id(read_u32(data, offset + 4)? & 0x00ff_ffff);
+    let length = read_u32(data, offset + 52)? as usize;
+    let start = offset + RULE_HEADER_LENGTH;
+    let body = data
+        .get(start..start + length)
+        .ok_or_else(|| SmartDecodeError(format!("rule at byte {offset} overruns the data")))?;
+
+    let value = match field.field_type() {
+        Some(FieldType::Text) => {
+            let units = body
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect::<Vec<u16>>();
+            let text = String::from_utf16(&units)
+                .map_err(|_| SmartDecodeError(format!("rule at byte {offset} is not UTF-16")))?;
+            RuleValue::Text(text)
+        }
+        Some(FieldType::Group) => RuleValue::Group(decode_criteria(body)?),
+        _ if length < INT_DATA_LENGTH => {
+            return Err(SmartDecodeError(format!(
+                "rule at byte {offset} has {length} bytes of data"
+            )));
+        }
+        Some(FieldType::Date) if operator == Operator::InTheLast => {
+            let count = read_i64(body, 8)?;
+            let unit = read_i64(body, 16)?;
+            let seconds = count
+                .unsigned_abs()
+                .checked_mul(unit.unsigned_abs())
+                .ok_or_else(|| {
+                    SmartDecodeError(format!("rule at byte {offset} is out of range"))
+                })?;
+            RuleValue::Within(Duration::from_secs(seconds))
+        }
+        Some(FieldType::Date) => {
+            RuleValue::Date(mac_time(read_i64(body, 0)?)?, mac_time(read_i64(body, 24)?)?)
+        }
+        Some(FieldType::Playlist) => {
+            RuleValue::Playlist(format!("{:016X}", read_i64(body, 0)? as u64))
+        }
+        _ => RuleValue::Integer(read_i64(body, 0)?, read_i64(body, 24)?),
+    };
+
+    let rule = Rule {
+        field,
+        operator,
+        negated: sign & 0x02 != 0,
+        value,
+    };
+
+    Ok((rule, start + length))
+}
+
+fn decode_criteria(criteria: &[u8]) -> Result<SmartCriteria, SmartDecodeError> {
+    if !criteria.starts_with(CRITERIA_MAGIC) {
+        return Err(SmartDecodeError(String::from("missing SLst header")));
+    }
+
+    let count = read_u32(criteria, 8)?;
+    let conjunction = match read_u32(criteria, 12)? {
+        1 => Conjunction::Any,
+        _ => Conjunction::All,
+    };
+
+    let mut rules = Vec::new();
+    let mut offset = CRITERIA_HEADER_LENGTH;
+    for _ in 0..count {
+        let (rule, next) = decode_rule(criteria, offset)?;
+        rules.push(rule);
+        offset = next;
+    }
+
+    Ok(SmartCriteria { conjunction, rules })
+}
+
+pub fn decode_smart_playlist(
+    info: &[u8],
+    criteria: &[u8],
+) -> Result<SmartPlaylist, SmartDecodeError> {
+    let rules_enabled = read_byte(info, 1)? == 1;
+    Ok(SmartPlaylist {
+        live_updating: read_byte(info, 0)? == 1,
+        match_checked_only: read_byte(info, 12)? == 1,
+        limit: decode_limit(info)?,
+        criteria: if rules_enabled {
+            Some(decode_criteria(criteria)?)
+        } else {
+            None
+        },
+    })
+}
+
+struct DataVisitor;
+
+impl<'de> Visitor<'de> for DataVisitor {
+    type Value = Option<Vec<u8>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("plist data")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(Some(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(Some(v))
+    }
+}
+
+/// serde's `Vec<u8>` impl expects a sequence, plist `<data>` comes through as
+/// bytes.
+pub fn deserialize_data<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_byte_buf(DataVisitor)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub fn info_blob(limit: Option<(u8, u8, u32)>, checked_only: bool) -> Vec<u8> {
+        let mut info = vec![0; 92];
+        info[0] = 1;
+        info[1] = 1;
+        if let Some((unit, selection, value)) = limit {
+            info[2] = 1;
+            info[3] = unit;
+            info[7] = selection;
+            info[8..12].copy_from_slice(&value.to_be_bytes());
+        }
+        info[12] = checked_only as u8;
+        info
+    }
+
+    pub fn text_rule(field: u32, operator: u32, negated: bool, value: &str) -> Vec<u8> {
+        let body = value
+            .encode_utf16()
+            .flat_map(|unit| unit.to_be_bytes())
+            .collect::<Vec<u8>>();
+        rule_blob(field, operator | 0x0100_0000 | ((negated as u32) << 25), body)
+    }
+
+    pub fn int_rule(field: u32, operator: u32, values: [i64; 4]) -> Vec<u8> {
+        let mut body = vec![0; INT_DATA_LENGTH];
+        for (i, value) in values.iter().enumerate() {
+            body[i * 8..i * 8 + 8].copy_from_slice(&value.to_be_bytes());
+        }
+        rule_blob(field, operator, body)
+    }
+
+    pub fn rule_blob(field: u32, operator: u32, body: Vec<u8>) -> Vec<u8> {
+        let mut rule = vec![0; RULE_HEADER_LENGTH];
+        rule[0..4].copy_from_slice(&field.to_be_bytes());
+        rule[4..8].copy_from_slice(&operator.to_be_bytes());
+        rule[52..56].copy_from_slice(&(body.len() as u32).to_be_bytes());
+        rule.extend(body);
+        rule
+    }
+
+    pub fn criteria_blob(any: bool, rules: &[Vec<u8>]) -> Vec<u8> {
+        let mut criteria = vec![0; CRITERIA_HEADER_LENGTH];
+        criteria[0..4].copy_from_slice(CRITERIA_MAGIC);
+        criteria[8..12].copy_from_slice(&(rules.len() as u32).to_be_bytes());
+        criteria[12..16].copy_from_slice(&(any as u32).to_be_bytes());
+        rules.iter().for_each(|rule| criteria.extend(rule));
+        criteria
+    }
+
+    #[test]
+    fn test_decode_smart_playlist() {
+        let info = info_blob(Some((3, 0x1c, 25)), true);
+        let criteria = criteria_blob(
+            false,
+            &[
+                text_rule(0x08, 0x02, false, "Rock"),
+                text_rule(0x04, 0x01, true, "Nickelback"),
+                int_rule(0x07, 0x100, [1970, 0, 0, 1979]),
+                int_rule(0x10, 0x200, [0x2dae_2dae_2dae_2dae, -2, 604_800, 0]),
+            ],
+        );
+
+        let smart = decode_smart_playlist(&info, &criteria).unwrap();
+        assert!(smart.live_updating && smart.match_checked_only);
+        assert_eq!(
+            smart.limit,
+            Some(Limit {
+                value: 25,
+                unit: LimitUnit::Items,
+                selection: Selection::HighestRating,
+                reverse: false,
+            })
+        );
+
+        let criteria = smart.criteria.unwrap();
+        assert_eq!(criteria.conjunction, Conjunction::All);
+        assert_eq!(
+            criteria.rules,
+            vec![
+                Rule {
+                    field: Field::Genre,
+                    operator: Operator::Contains,
+                    negated: false,
+                    value: RuleValue::Text(String::from("Rock")),
+                },
+                Rule {
+                    field: Field::Artist,
+                    operator: Operator::Is,
+                    negated: true,
+                    value: RuleValue::Text(String::from("Nickelback")),
+                },
+                Rule {
+                    field: Field::Year,
+                    operator: Operator::InRange,
+                    negated: false,
+                    value: RuleValue::Integer(1970, 1979),
+                },
+                Rule {
+                    field: Field::DateAdded,
+                    operator: Operator::InTheLast,
+                    negated: false,
+                    value: RuleValue::Within(Duration::from_secs(2 * 604_800)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_nested_rules() {
+        let info = info_blob(None, false);
+        let group = criteria_blob(
+            true,
+            &[
+                text_rule(0x08, 0x01, false, "Rock"),
+                text_rule(0x08, 0x01, false, "Metal"),
+            ],
+        );
+        let criteria = criteria_blob(
+            false,
+            &[rule_blob(0x00, 0x01, group), int_rule(0x19, 0x10, [60, 0, 0, 0])],
+        );
+
+        let criteria = decode_smart_playlist(&info, &criteria).unwrap().criteria.unwrap();
+        let genre = |value: &str| Rule {
+            field: Field::Genre,
+            operator: Operator::Is,
+            negated: false,
+            value: RuleValue::Text(String::from(value)),
+        };
+        assert_eq!(
+            criteria.rules[0].value,
+            RuleValue::Group(SmartCriteria {
+                conjunction: Conjunction::Any,
+                rules: vec![genre("Rock"), genre("Metal")],
+            })
+        );
+        assert_eq!(criteria.rules[1].field, Field::Rating);
+    }
+
+    #[test]
+    fn test_decode_smart_playlist_errors() {
+        let info = info_blob(None, false);
+        let err = decode_smart_playlist(&info, b"nope").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid smart playlist data: missing SLst header");
+
+        let mut criteria = criteria_blob(true, &[int_rule(0x07, 0x01, [1999, 0, 0, 0])]);
+        criteria.truncate(criteria.len() - 10);
+        assert!(decode_smart_playlist(&info, &criteria).is_err());
+    }
+
+    #[test]
+    fn test_mac_time() {
+        assert_eq!(mac_time(MAC_EPOCH_OFFSET as i64), Ok(UNIX_EPOCH));
+        assert_eq!(
+            mac_time(MAC_EPOCH_OFFSET as i64 + 60),
+            Ok(UNIX_EPOCH + Duration::from_secs(60))
+        );
+        assert_eq!(
+            mac_time(i64::MIN).unwrap_err().to_string(),
+            format!("Invalid smart playlist data: date {} is out of range", i64::MIN)
+        );
+    }
+
+    #[test]
+    fn test_decode_out_of_range_dates() {
+        let info = info_blob(None, false);
+        for rule in [
+            int_rule(0x10, 0x01, [i64::MIN, 0, 0, 0]),
+            int_rule(0x10, 0x100, [0, 0, 0, i64::MIN]),
+            int_rule(0x10, 0x200, [0, i64::MIN, i64::MAX, 0]),
+        ] {
+            let criteria = criteria_blob(false, &[rule]);
+            assert!(decode_smart_playlist(&info, &criteria).is_err());
+        }
+    }
+}
@@ -0,0 +1,632 @@
+//! smart_eval
+//!
+//! Evaluates decoded smart playlist rules against the library so the
+//! membership iTunes exported can be checked against what the rules say.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use crate::export::ExportOptions;
+use crate::library::Playlist;
+use crate::normalize::Normalization;
+use crate::smart::{
+    Conjunction, Field, FieldType, Limit, LimitUnit, Operator, Rule, RuleValue, Selection,
+    SmartCriteria, SmartPlaylist,
+};
+use crate::track::Track;
+
+static SECONDS_PER_DAY: u64 = 86_400;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Unsupported {
+    Field(Field),
+    Operator(Field, Operator),
+    Selection(Selection),
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Unsupported::Field(field) => write!(f, "unsupported field {:?}", field),
+            Unsupported::Operator(field, op) => {
+                write!(f, "unsupported operator {:?} for field {:?}", op, field)
+            }
+            Unsupported::Selection(selection) => {
+                write!(f, "limit selection {:?} can't be reproduced", selection)
+            }
+        }
+    }
+}
+
+pub struct SmartContext<'a> {
+    tracks: &'a HashMap<String, Track>,
+    // Playlist rules refer to other playlists by persistent ID.
+    playlists: HashMap<&'a str, HashSet<&'a str>>,
+    now: SystemTime,
+}
+
+impl<'a> SmartContext<'a> {
+    pub fn new(
+        tracks: &'a HashMap<String, Track>,
+        playlists: &'a [Playlist],
+        now: SystemTime,
+    ) -> SmartContext<'a> {
+        let playlists = playlists
+            .iter()
+            .filter_map(|playlist| {
+                let id = playlist.persistent_id.as_deref()?;
+                let members = playlist.playlist_items.iter().map(|item| item.track_id.as_str());
+                Some((id, members.collect()))
+            })
+            .collect();
+
+        SmartContext {
+            tracks,
+            playlists,
+            now,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MembershipDiff {
+    // In the exported playlist but not matched by the rules.
+    pub missing: Vec<String>,
+    // Matched by the rules but not in the exported playlist.
+    pub unexpected: Vec<String>,
+}
+
+impl MembershipDiff {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty()
+    }
+}
+
+fn text_field(field: Field, track: &Track) -> Option<&str> {
+    let value = match field {
+        Field::Name => &track.name,
+        Field::Album => &track.album,
+        Field::Artist => &track.artist,
+        Field::AlbumArtist => &track.album_artist,
+        Field::Composer => &track.composer,
+        Field::Genre => &track.genre,
+        Field::Kind => &track.kind,
+        Field::Comments => &track.comments,
+        Field::Grouping => &track.grouping,
+        Field::SortName => &track.sort_name,
+        Field::SortAlbum => &track.sort_album,
+        Field::SortArtist => &track.sort_artist,
+        Field::SortAlbumArtist => &track.sort_album_artist,
+        Field::SortComposer => &track.sort_composer,
+        _ => return None,
+    };
+
+    Some(value.as_deref().unwrap_or(""))
+}
+
+// iTunes treats a missing number as 0, e.g. "Play Count is 0" matches
+// tracks that have never been played and so have no Play Count key.
+fn int_field(field: Field, track: &Track) -> Option<i64> {
+    let value = match field {
+        Field::BitRate => track.bit_rate.map(i64::from),
+        Field::SampleRate => track.sample_rate.map(i64::from),
+        Field::Year => track.year.map(i64::from),
+        Field::TrackNumber => track.track_number.map(i64::from),
+        Field::DiscNumber => track.disc_number.map(i64::from),
+        Field::Size => track.size.map(|size| size as i64),
+        Field::Time => Some(i64::from(track.duration)),
+        Field::PlayCount => track.play_count.map(i64::from),
+        Field::SkipCount => track.skip_count.map(i64::from),
+        Field::Rating => track.rating.map(i64::from),
+        Field::Bpm => track.bpm.map(i64::from),
+        Field::Compilation => Some(track.compilation as i64),
+        Field::Loved => Some(track.loved as i64),
+        _ => return None,
+    };
+
+    Some(value.unwrap_or(0))
+}
+
+fn date_field(field: Field, track: &Track) -> Option<SystemTime> {
+    let value = match field {
+        Field::DateAdded => track.date_added,
+        Field::DateModified => track.date_modified,
+        Field::LastPlayed => track.play_date_utc,
+        Field::LastSkipped => track.skip_date,
+        _ => None,
+    };
+
+    value.map(SystemTime::from)
+}
+
+fn day_of(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() / SECONDS_PER_DAY)
+}
+
+fn text_matches(rule: &Rule, actual: &str, expected: &str) -> Result<bool, Unsupported> {
+    // iTunes string comparisons are case insensitive, and the library keeps
+    // names however they were typed so accents can be either form.
+    let actual = Normalization::Nfc.apply(actual).to_lowercase();
+    let expected = Normalization::Nfc.apply(expected).to_lowercase();
+    match rule.operator {
+        Operator::Is => Ok(actual == expected),
+        Operator::Contains => Ok(actual.contains(&expected)),
+        Operator::StartsWith => Ok(actual.starts_with(&expected)),
+        Operator::EndsWith => Ok(actual.ends_with(&expected)),
+        op => Err(Unsupported::Operator(rule.field, op)),
+    }
+}
+
+fn int_matches(rule: &Rule, actual: i64, low: i64, high: i64) -> Result<bool, Unsupported> {
+    match rule.operator {
+        Operator::Is => Ok(actual == low),
+        Operator::GreaterThan => Ok(actual > low),
+        Operator::LessThan => Ok(actual < low),
+        Operator::InRange => Ok(low <= actual && actual <= high),
+        op => Err(Unsupported::Operator(rule.field, op)),
+    }
+}
+
+fn date_matches(
+    rule: &Rule,
+    actual: SystemTime,
+    low: SystemTime,
+    high: SystemTime,
+) -> Result<bool, Unsupported> {
+    match rule.operator {
+        Operator::Is => Ok(day_of(actual) == day_of(low)),
+        Operator::GreaterThan => Ok(actual > low),
+        Operator::LessThan => Ok(actual < low),
+        Operator::InRange => Ok(low <= actual && actual <= high),
+        op => Err(Unsupported::Operator(rule.field, op)),
+    }
+}
+
+fn within(ctx: &SmartContext, actual: SystemTime, window: Duration) -> bool {
+    // Dates in the future are trivially within the window.
+    ctx.now
+        .duration_since(actual)
+        .map_or(true, |age| age <= window)
+}
+
+pub fn matches_rule(
+    ctx: &SmartContext,
+    rule: &Rule,
+    track_id: &str,
+    track: &Track,
+) -> Result<bool, Unsupported> {
+    let field = rule.field;
+    let matched = match (field.field_type(), &rule.value) {
+        (Some(FieldType::Text), RuleValue::Text(expected)) => {
+            let actual = text_field(field, track).ok_or(Unsupported::Field(field))?;
+            text_matches(rule, actual, expected)?
+        }
+        (Some(FieldType::Integer | FieldType::Boolean), RuleValue::Integer(low, high)) => {
+            let actual = int_field(field, track).ok_or(Unsupported::Field(field))?;
+            int_matches(rule, actual, *low, *high)?
+        }
+        (Some(FieldType::Date), RuleValue::Date(low, high)) => match date_field(field, track) {
+            Some(actual) => date_matches(rule, actual, *low, *high)?,
+            None => false,
+        },
+        (Some(FieldType::Date), RuleValue::Within(window)) => {
+            date_field(field, track).is_some_and(|actual| within(ctx, actual, *window))
+        }
+        (Some(FieldType::Playlist), RuleValue::Playlist(id)) => match rule.operator {
+            Operator::Is => ctx
+                .playlists
+                .get(id.as_str())
+                .is_some_and(|members| members.contains(&track_id)),
+            op => return Err(Unsupported::Operator(field, op)),
+        },
+        (Some(FieldType::Group), RuleValue::Group(group)) => {
+            matches_criteria(ctx, group, track_id, track)?
+        }
+        _ => return Err(Unsupported::Field(field)),
+    };
+
+    Ok(matched != rule.negated)
+}
+
+fn matches_criteria(
+    ctx: &SmartContext,
+    criteria: &SmartCriteria,
+    track_id: &str,
+    track: &Track,
+) -> Result<bool, Unsupported> {
+    let mut results = criteria
+        .rules
+        .iter()
+        .map(|rule| matches_rule(ctx, rule, track_id, track));
+    match criteria.conjunction {
+        Conjunction::All => results.try_fold(true, |acc, r| r.map(|m| acc && m)),
+        Conjunction::Any => results.try_fold(false, |acc, r| r.map(|m| acc || m)),
+    }
+}
+
+fn limit_budget(limit: &Limit, track: &Track) -> u64 {
+    match limit.unit {
+        LimitUnit::Items => 1,
+        LimitUnit::Minutes | LimitUnit::Hours => track.duration.max(0) as u64,
+        LimitUnit::Megabytes | LimitUnit::Gigabytes => track.size.unwrap_or(0),
+    }
+}
+
+fn apply_limit<'a>(
+    limit: &Limit,
+    mut matched: Vec<(&'a str, &'a Track)>,
+) -> Result<Vec<(&'a str, &'a Track)>, Unsupported> {
+    let text = |track: &Track, field: Field| text_field(field, track).unwrap_or("").to_lowercase();
+    match limit.selection {
+        Selection::Name => matched.sort_by_cached_key(|(_, t)| text(t, Field::Name)),
+        Selection::Album => matched.sort_by_cached_key(|(_, t)| text(t, Field::Album)),
+        Selection::Artist => matched.sort_by_cached_key(|(_, t)| text(t, Field::Artist)),
+        Selection::Genre => matched.sort_by_cached_key(|(_, t)| text(t, Field::Genre)),
+        Selection::HighestRating => matched.sort_by_key(|(_, t)| Reverse(t.rating)),
+        Selection::MostOftenPlayed => matched.sort_by_key(|(_, t)| Reverse(t.play_count)),
+        Selection::MostRecentlyPlayed => {
+            matched.sort_by_key(|(_, t)| Reverse(t.play_date_utc.map(SystemTime::from)))
+        }
+        Selection::MostRecentlyAdded => {
+            matched.sort_by_key(|(_, t)| Reverse(t.date_added.map(SystemTime::from)))
+        }
+        selection @ (Selection::Random | Selection::Unknown(_)) => {
+            return Err(Unsupported::Selection(selection))
+        }
+    }
+
+    if limit.reverse {
+        matched.reverse();
+    }
+
+    let value = u64::from(limit.value);
+    let max = match limit.unit {
+        LimitUnit::Items => value,
+        LimitUnit::Minutes => value * 60 * 1000,
+        LimitUnit::Hours => value * 60 * 60 * 1000,
+        LimitUnit::Megabytes => value * 1024 * 1024,
+        LimitUnit::Gigabytes => value * 1024 * 1024 * 1024,
+    };
+
+    let mut used = 0;
+    Ok(matched
+        .into_iter()
+        .take_while(|(_, track)| {
+            used += limit_budget(limit, track);
+            used <= max
+        })
+        .collect())
+}
+
+/// Returns the track IDs the smart playlist should contain.
+pub fn evaluate<'a>(
+    ctx: &SmartContext<'a>,
+    smart: &SmartPlaylist,
+) -> Result<Vec<&'a str>, Unsupported> {
+    let mut candidates = ctx
+        .tracks
+        .iter()
+        .map(|(id, track)| (id.as_str(), track))
+        .filter(|(_, track)| !(smart.match_checked_only && track.disabled))
+        .collect::<Vec<(&str, &Track)>>();
+
+    // HashMap order is random, keep the limit tie-breaks deterministic.
+    // Shorter first puts numeric IDs in numeric order.
+    candidates.sort_by_key(|(id, _)| (id.len(), *id));
+
+    let mut matched = Vec::new();
+    for (id, track) in candidates {
+        let is_match = match &smart.criteria {
+            None => true,
+            Some(criteria) => matches_criteria(ctx, criteria, id, track)?,
+        };
+
+        if is_match {
+            matched.push((id, track));
+        }
+    }
+
+    if let Some(limit) = &smart.limit {
+        matched = apply_limit(limit, matched)?;
+    }
+
+    Ok(matched.into_iter().map(|(id, _)| id).collect())
+}
+
+pub fn compare_membership(playlist: &Playlist, evaluated: &[&str]) -> MembershipDiff {
+    let exported = playlist
+        .playlist_items
+        .iter()
+        .map(|item| item.track_id.as_str())
+        .collect::<HashSet<&str>>();
+    let evaluated_set = evaluated.iter().copied().collect::<HashSet<&str>>();
+
+    let mut missing = exported.difference(&evaluated_set).copied().collect::<Vec<&str>>();
+    let mut unexpected = evaluated_set.difference(&exported).copied().collect::<Vec<&str>>();
+    missing.sort_unstable_by_key(|id| (id.len(), *id));
+    unexpected.sort_unstable_by_key(|id| (id.len(), *id));
+    MembershipDiff {
+        missing: missing.into_iter().map(String::from).collect(),
+        unexpected: unexpected.into_iter().map(String::from).collect(),
+    }
+}
+
+/// Recomputes a smart playlist and reports any difference from what iTunes
+/// exported. Returns true if the membership matched. Playlists that aren't
+/// live updating are skipped, their tracks were frozen when the rules last
+/// ran so they're expected to differ.
+pub fn check_smart_playlist(args: &ExportOptions, ctx: &SmartContext, playlist: &Playlist) -> bool {
+    let name = &playlist.name;
+    let smart = match &playlist.smart {
+        None => return true,
+        Some(Err(err)) => {
+            args.log.warn(&format!("Smart playlist {name}: {err}"));
+            return false;
+        }
+        Some(Ok(smart)) => smart,
+    };
+
+    if !smart.live_updating {
+        if args.verbose > 1 {
+            args.log.info(&format!("Smart playlist {name} isn't live updating, not checking it."));
+        }
+        return true;
+    }
+
+    let evaluated = match evaluate(ctx, smart) {
+        Ok(evaluated) => evaluated,
+        Err(unsupported) => {
+            if args.verbose > 0 {
+                args.log.warn(&format!("Smart playlist {name} can't be checked: {unsupported}"));
+            }
+            return true;
+        }
+    };
+
+    let diff = compare_membership(playlist, &evaluated);
+    if diff.is_empty() {
+        if args.verbose > 1 {
+            args.log.info(&format!("Smart playlist {name} matches its rules."));
+        }
+        return true;
+    }
+
+    let track_name = |id: &String| {
+        ctx.tracks
+            .get(id)
+            .map_or_else(|| format!("Track {id}"), |track| track.get_name().to_string())
+    };
+
+    args.log.warn(&format!(
+        "Smart playlist {name} differs from its rules: {} exported but not matched, {} matched but not exported.",
+        diff.missing.len(),
+        diff.unexpected.len()
+    ));
+
+    if args.verbose > 0 {
+        diff.missing
+            .iter()
+            .for_each(|id| args.log.warn(&format!("  - {}", track_name(id))));
+        diff.unexpected
+            .iter()
+            .for_each(|id| args.log.warn(&format!("  + {}", track_name(id))));
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::PlaylistItem;
+    use crate::smart::decode_smart_playlist;
+    use crate::smart::tests::{criteria_blob, info_blob, int_rule, rule_blob, text_rule};
+
+    fn library() -> HashMap<String, Track> {
+        HashMap::from([
+            (String::from("1"), Track {
+                name: Some(String::from("Kashmir")),
+                artist: Some(String::from("Led Zeppelin")),
+                genre: Some(String::from("Hard Rock")),
+                year: Some(1975),
+                rating: Some(100),
+                duration: 60_000,
+                ..Default::default()
+            }),
+            (String::from("2"), Track {
+                name: Some(String::from("Photograph")),
+                artist: Some(String::from("Nickelback")),
+                genre: Some(String::from("Rock")),
+                year: Some(2005),
+                rating: Some(20),
+                duration: 60_000,
+                ..Default::default()
+            }),
+            (String::from("3"), Track {
+                name: Some(String::from("So What")),
+                artist: Some(String::from("Miles Davis")),
+                genre: Some(String::from("Jazz")),
+                year: Some(1959),
+                rating: Some(80),
+                duration: 60_000,
+                disabled: true,
+                ..Default::default()
+            }),
+        ])
+    }
+
+    fn smart_playlist(
+        name: &str,
+        info: Vec<u8>,
+        criteria: Vec<u8>,
+        items: &[&str],
+    ) -> Playlist {
+        Playlist {
+            name: String::from(name),
+            playlist_items: items
+                .iter()
+                .map(|id| PlaylistItem { track_id: id.to_string() })
+                .collect(),
+            smart: Some(decode_smart_playlist(&info, &criteria)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_evaluate_all() {
+        let tracks = library();
+        let playlists = vec![smart_playlist(
+            "Rock",
+            info_blob(None, false),
+            criteria_blob(
+                false,
+                &[
+                    text_rule(0x08, 0x02, false, "rock"),
+                    text_rule(0x04, 0x01, true, "Nickelback"),
+                ],
+            ),
+            &["1"],
+        )];
+
+        let ctx = SmartContext::new(&tracks, &playlists, SystemTime::now());
+        let smart = playlists[0].smart.as_ref().unwrap().as_ref().unwrap();
+        assert_eq!(evaluate(&ctx, smart), Ok(vec!["1"]));
+    }
+
+    #[test]
+    fn test_evaluate_any_with_limit() {
+        let tracks = library();
+        let playlists = vec![smart_playlist(
+            "Old and good",
+            info_blob(Some((1, 0x1c, 1)), true),
+            criteria_blob(
+                true,
+                &[
+                    int_rule(0x07, 0x40, [1980, 0, 0, 0]),
+                    int_rule(0x19, 0x10, [10, 0, 0, 0]),
+                ],
+            ),
+            &["1", "2"],
+        )];
+
+        let ctx = SmartContext::new(&tracks, &playlists, SystemTime::now());
+        let smart = playlists[0].smart.as_ref().unwrap().as_ref().unwrap();
+        // Track 3 matches but is unchecked, the 1 minute limit only leaves
+        // room for the highest rated of the rest.
+        let evaluated = evaluate(&ctx, smart).unwrap();
+        assert_eq!(evaluated, vec!["1"]);
+        assert_eq!(
+            compare_membership(&playlists[0], &evaluated),
+            MembershipDiff {
+                missing: vec![String::from("2")],
+                unexpected: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_evaluate_nested() {
+        let tracks = library();
+        // (genre is Jazz or artist is Nickelback) and year > 1950
+        let group = criteria_blob(
+            true,
+            &[
+                text_rule(0x08, 0x01, false, "Jazz"),
+                text_rule(0x04, 0x01, false, "Nickelback"),
+            ],
+        );
+        let playlists = vec![smart_playlist(
+            "Nested",
+            info_blob(None, false),
+            criteria_blob(
+                false,
+                &[rule_blob(0x00, 0x01, group), int_rule(0x07, 0x10, [1950, 0, 0, 0])],
+            ),
+            &["2", "3"],
+        )];
+
+        let ctx = SmartContext::new(&tracks, &playlists, SystemTime::now());
+        let smart = playlists[0].smart.as_ref().unwrap().as_ref().unwrap();
+        assert_eq!(evaluate(&ctx, smart), Ok(vec!["2", "3"]));
+    }
+
+    #[test]
+    fn test_evaluate_unsupported() {
+        let tracks = library();
+        let ctx = SmartContext::new(&tracks, &[], SystemTime::now());
+        let smart = SmartPlaylist {
+            live_updating: true,
+            match_checked_only: false,
+            limit: None,
+            criteria: Some(SmartCriteria {
+                conjunction: Conjunction::All,
+                rules: vec![Rule {
+                    field: Field::Unknown(0x99),
+                    operator: Operator::Is,
+                    negated: false,
+                    value: RuleValue::Integer(1, 0),
+                }],
+            }),
+        };
+
+        assert_eq!(
+            evaluate(&ctx, &smart),
+            Err(Unsupported::Field(Field::Unknown(0x99)))
+        );
+    }
+
+    #[test]
+    fn test_matches_rule_playlist_and_dates() {
+        let tracks = library();
+        let playlists = vec![Playlist {
+            persistent_id: Some(String::from("00000000000000FF")),
+            playlist_items: vec![PlaylistItem { track_id: String::from("2") }],
+            ..Default::default()
+        }];
+
+        let now = SystemTime::now();
+        let ctx = SmartContext::new(&tracks, &playlists, now);
+        let in_playlist = Rule {
+            field: Field::Playlist,
+            operator: Operator::Is,
+            negated: false,
+            value: RuleValue::Playlist(String::from("00000000000000FF")),
+        };
+        assert_eq!(matches_rule(&ctx, &in_playlist, "2", &tracks["2"]), Ok(true));
+        assert_eq!(matches_rule(&ctx, &in_playlist, "1", &tracks["1"]), Ok(false));
+
+        let recently_added = Rule {
+            field: Field::DateAdded,
+            operator: Operator::InTheLast,
+            negated: false,
+            value: RuleValue::Within(Duration::from_secs(SECONDS_PER_DAY)),
+        };
+        let track = Track {
+            date_added: Some((now - Duration::from_secs(60)).into()),
+            ..Default::default()
+        };
+        assert_eq!(matches_rule(&ctx, &recently_added, "4", &track), Ok(true));
+        assert_eq!(matches_rule(&ctx, &recently_added, "1", &tracks["1"]), Ok(false));
+    }
+
+    #[test]
+    fn test_check_smart_playlist_live_updating() {
+        let tracks = library();
+        let ctx = SmartContext::new(&tracks, &[], SystemTime::now());
+        let args = ExportOptions::default();
+        let criteria = criteria_blob(false, &[text_rule(0x08, 0x01, false, "Jazz")]);
+        let mut playlist = smart_playlist("Jazz", info_blob(None, false), criteria, &["1"]);
+        assert!(!check_smart_playlist(&args, &ctx, &playlist));
+
+        if let Some(Ok(smart)) = &mut playlist.smart {
+            smart.live_updating = false;
+        }
+        assert!(check_smart_playlist(&args, &ctx, &playlist));
+    }
+}
@@ -0,0 +1,647 @@
+//! smart_export
+//!
+//! Translates decoded smart playlist rules into the rule based playlist
+//! formats of other players, Kodi `.xsp` and Navidrome `.nsp`, so they keep
+//! updating on the target instead of being frozen track lists.
+//!
+//! "Match only checked items" is ignored since neither player has the
+//! concept of unchecked tracks.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use serde_json::{json, Map, Value};
+
+use crate::export::ExportOptions;
+use crate::library::Playlist;
+use crate::smart::{
+    Conjunction, Field, LimitUnit, Operator, Rule, RuleValue, Selection, SmartCriteria,
+    SmartPlaylist,
+};
+use crate::xml::escape_xml;
+
+static SECONDS_PER_DAY: u64 = 86_400;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmartFormat {
+    Kodi,
+    Navidrome,
+}
+
+impl SmartFormat {
+    pub fn from_arg(arg: &str) -> Option<SmartFormat> {
+        match arg {
+            "kodi" => Some(SmartFormat::Kodi),
+            "navidrome" => Some(SmartFormat::Navidrome),
+            _ => None,
+        }
+    }
+}
+
+fn days(window: &Duration) -> u64 {
+    // Round up, "in the last 36 hours" shouldn't become 1 day.
+    window.as_secs().div_ceil(SECONDS_PER_DAY)
+}
+
+fn date_string(time: &SystemTime) -> String {
+    plist::Date::from(*time).to_xml_format()[..10].to_string()
+}
+
+fn kodi_field(field: Field) -> Option<&'static str> {
+    match field {
+        Field::Name => Some("title"),
+        Field::Album => Some("album"),
+        Field::Artist => Some("artist"),
+        Field::AlbumArtist => Some("albumartist"),
+        Field::Genre => Some("genre"),
+        Field::Comments => Some("comment"),
+        Field::Year => Some("year"),
+        Field::TrackNumber => Some("tracknumber"),
+        Field::Time => Some("time"),
+        Field::PlayCount => Some("playcount"),
+        Field::Rating => Some("userrating"),
+        Field::DateAdded => Some("dateadded"),
+        Field::LastPlayed => Some("lastplayed"),
+        Field::Playlist => Some("playlist"),
+        _ => None,
+    }
+}
+
+fn kodi_operator(rule: &Rule) -> Option<&'static str> {
+    match (rule.operator, rule.negated) {
+        (Operator::Is, false) => Some("is"),
+        (Operator::Is, true) => Some("isnot"),
+        (Operator::Contains, false) => Some("contains"),
+        (Operator::Contains, true) => Some("doesnotcontain"),
+        (Operator::StartsWith, false) => Some("startswith"),
+        (Operator::EndsWith, false) => Some("endswith"),
+        (Operator::GreaterThan, false) => match rule.value {
+            RuleValue::Date(..) => Some("after"),
+            _ => Some("greaterthan"),
+        },
+        (Operator::LessThan, false) => match rule.value {
+            RuleValue::Date(..) => Some("before"),
+            _ => Some("lessthan"),
+        },
+        (Operator::InTheLast, false) => Some("inthelast"),
+        (Operator::InTheLast, true) => Some("notinthelast"),
+        _ => None,
+    }
+}
+
+// Kodi ratings are 0-10 and times are in seconds.
+fn kodi_number(field: Field, value: i64) -> i64 {
+    match field {
+        Field::Rating => value / 10,
+        Field::Time => value / 1000,
+        _ => value,
+    }
+}
+
+fn kodi_rule(
+    rule: &Rule,
+    conjunction: Conjunction,
+    playlist_names: &HashMap<&str, &str>,
+) -> Result<Vec<String>, String> {
+    if let RuleValue::Group(_) = rule.value {
+        return Err(String::from("Kodi has no nested groups of rules"));
+    }
+
+    let field = kodi_field(rule.field)
+        .ok_or_else(|| format!("Kodi has no equivalent of the {:?} field", rule.field))?;
+
+    let xml_rule = |operator: &str, value: String| {
+        format!(
+            "    <rule field=\"{field}\" operator=\"{operator}\">\n        \
+            <value>{}</value>\n    </rule>",
+            escape_xml(&value)
+        )
+    };
+
+    // Kodi has no range operator but with match all a range is just
+    // two rules. Kodi's comparisons are exclusive and iTunes' ranges
+    // aren't, so the ends are widened by one.
+    if rule.operator == Operator::InRange {
+        let day = Duration::from_secs(SECONDS_PER_DAY);
+        return match (&rule.value, conjunction, rule.negated) {
+            (RuleValue::Integer(low, high), Conjunction::All, false) => Ok(vec![
+                xml_rule("greaterthan", (kodi_number(rule.field, *low) - 1).to_string()),
+                xml_rule("lessthan", (kodi_number(rule.field, *high) + 1).to_string()),
+            ]),
+            (RuleValue::Date(low, high), Conjunction::All, false) => Ok(vec![
+                xml_rule("after", date_string(&low.checked_sub(day).unwrap_or(*low))),
+                xml_rule("before", date_string(&high.checked_add(day).unwrap_or(*high))),
+            ]),
+            _ => Err(format!("Kodi can't express a range on {:?} here", rule.field)),
+        };
+    }
+
+    let operator = kodi_operator(rule).ok_or_else(|| {
+        format!(
+            "Kodi has no equivalent of {}{:?} on {:?}",
+            if rule.negated { "not " } else { "" },
+            rule.operator,
+            rule.field
+        )
+    })?;
+
+    let value = match &rule.value {
+        RuleValue::Text(text) => text.clone(),
+        RuleValue::Integer(value, _) => kodi_number(rule.field, *value).to_string(),
+        RuleValue::Date(date, _) => date_string(date),
+        RuleValue::Within(window) => format!("{} days", days(window)),
+        RuleValue::Playlist(id) => playlist_names
+            .get(id.as_str())
+            .map(|name| name.to_string())
+            .ok_or_else(|| format!("Playlist {id} is not in the library"))?,
+        RuleValue::Group(_) => unreachable!("groups are turned away above"),
+    };
+
+    Ok(vec![xml_rule(operator, value)])
+}
+
+fn kodi_order(selection: Selection) -> Option<(&'static str, bool)> {
+    match selection {
+        Selection::Random => Some(("random", false)),
+        Selection::Name => Some(("title", false)),
+        Selection::Album => Some(("album", false)),
+        Selection::Artist => Some(("artist", false)),
+        Selection::Genre => Some(("genre", false)),
+        Selection::HighestRating => Some(("userrating", true)),
+        Selection::MostOftenPlayed => Some(("playcount", true)),
+        Selection::MostRecentlyPlayed => Some(("lastplayed", true)),
+        Selection::MostRecentlyAdded => Some(("dateadded", true)),
+        Selection::Unknown(_) => None,
+    }
+}
+
+/// Renders a Kodi smart playlist, or the list of criteria that Kodi can't
+/// express.
+pub fn to_xsp(
+    name: &str,
+    smart: &SmartPlaylist,
+    playlists: &[Playlist],
+) -> Result<String, Vec<String>> {
+    let playlist_names = playlists
+        .iter()
+        .filter_map(|p| Some((p.persistent_id.as_deref()?, p.name.as_str())))
+        .collect::<HashMap<&str, &str>>();
+
+    let mut problems = Vec::new();
+    let mut lines = vec![
+        String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\" ?>"),
+        String::from("<smartplaylist type=\"songs\">"),
+        format!("    <name>{}</name>", escape_xml(name)),
+    ];
+
+    let conjunction = smart
+        .criteria
+        .as_ref()
+        .map_or(Conjunction::All, |criteria| criteria.conjunction);
+    lines.push(format!(
+        "    <match>{}</match>",
+        match conjunction {
+            Conjunction::All => "all",
+            Conjunction::Any => "one",
+        }
+    ));
+
+    let rules = smart.criteria.iter().flat_map(|criteria| &criteria.rules);
+    for rule in rules {
+        match kodi_rule(rule, conjunction, &playlist_names) {
+            Ok(rule_lines) => lines.extend(rule_lines),
+            Err(problem) => problems.push(problem),
+        }
+    }
+
+    if let Some(limit) = &smart.limit {
+        if limit.unit != LimitUnit::Items {
+            problems.push(format!("Kodi can only limit by items, not {:?}", limit.unit));
+        }
+
+        match kodi_order(limit.selection) {
+            Some((order, descending)) => {
+                let descending = descending != limit.reverse;
+                lines.push(format!("    <limit>{}</limit>", limit.value));
+                lines.push(format!(
+                    "    <order direction=\"{}\">{order}</order>",
+                    if descending { "descending" } else { "ascending" }
+                ));
+            }
+            None => problems.push(format!("Unknown limit selection {:?}", limit.selection)),
+        }
+    }
+
+    lines.push(String::from("</smartplaylist>\n"));
+    if problems.is_empty() {
+        Ok(lines.join("\n"))
+    } else {
+        Err(problems)
+    }
+}
+
+fn navidrome_field(field: Field) -> Option<&'static str> {
+    match field {
+        Field::Name => Some("title"),
+        Field::Album => Some("album"),
+        Field::Artist => Some("artist"),
+        Field::AlbumArtist => Some("albumartist"),
+        Field::Composer => Some("composer"),
+        Field::Genre => Some("genre"),
+        Field::Comments => Some("comment"),
+        Field::Grouping => Some("grouping"),
+        Field::SortName => Some("sorttitle"),
+        Field::SortAlbum => Some("sortalbum"),
+        Field::SortArtist => Some("sortartist"),
+        Field::SortAlbumArtist => Some("sortalbumartist"),
+        Field::BitRate => Some("bitrate"),
+        Field::Year => Some("year"),
+        Field::TrackNumber => Some("tracknumber"),
+        Field::DiscNumber => Some("discnumber"),
+        Field::Size => Some("size"),
+        Field::Time => Some("duration"),
+        Field::PlayCount => Some("playcount"),
+        Field::Rating => Some("rating"),
+        Field::Bpm => Some("bpm"),
+        Field::DateAdded => Some("dateadded"),
+        Field::DateModified => Some("datemodified"),
+        Field::LastPlayed => Some("lastplayed"),
+        Field::Compilation => Some("compilation"),
+        Field::Loved => Some("loved"),
+        _ => None,
+    }
+}
+
+fn navidrome_operator(rule: &Rule) -> Option<&'static str> {
+    match (rule.operator, rule.negated) {
+        (Operator::Is, false) => Some("is"),
+        (Operator::Is, true) => Some("isNot"),
+        (Operator::Contains, false) => Some("contains"),
+        (Operator::Contains, true) => Some("notContains"),
+        (Operator::StartsWith, false) => Some("startsWith"),
+        (Operator::EndsWith, false) => Some("endsWith"),
+        (Operator::GreaterThan, false) => match rule.value {
+            RuleValue::Date(..) => Some("after"),
+            _ => Some("gt"),
+        },
+        (Operator::LessThan, false) => match rule.value {
+            RuleValue::Date(..) => Some("before"),
+            _ => Some("lt"),
+        },
+        (Operator::InRange, false) => Some("inTheRange"),
+        (Operator::InTheLast, false) => Some("inTheLast"),
+        (Operator::InTheLast, true) => Some("notInTheLast"),
+        _ => None,
+    }
+}
+
+// Navidrome ratings are 0-5 stars and durations are in seconds.
+fn navidrome_number(field: Field, value: i64) -> Value {
+    match field {
+        Field::Rating => json!(value / 20),
+        Field::Time => json!(value / 1000),
+        Field::Compilation | Field::Loved => json!(value != 0),
+        _ => json!(value),
+    }
+}
+
+fn navidrome_rule(rule: &Rule) -> Result<Value, String> {
+    let field = navidrome_field(rule.field)
+        .ok_or_else(|| format!("Navidrome has no equivalent of the {:?} field", rule.field))?;
+    let operator = navidrome_operator(rule).ok_or_else(|| {
+        format!(
+            "Navidrome has no equivalent of {}{:?} on {:?}",
+            if rule.negated { "not " } else { "" },
+            rule.operator,
+            rule.field
+        )
+    })?;
+
+    let value = match (&rule.value, rule.operator) {
+        (RuleValue::Text(text), _) => json!(text),
+        (RuleValue::Integer(low, high), Operator::InRange) => json!([
+            navidrome_number(rule.field, *low),
+            navidrome_number(rule.field, *high)
+        ]),
+        (RuleValue::Integer(value, _), _) => navidrome_number(rule.field, *value),
+        (RuleValue::Date(low, high), Operator::InRange) => {
+            json!([date_string(low), date_string(high)])
+        }
+        (RuleValue::Date(date, _), _) => json!(date_string(date)),
+        (RuleValue::Within(window), _) => json!(days(window)),
+        (RuleValue::Playlist(_), _) => {
+            return Err(String::from("Navidrome playlist rules need Navidrome playlist IDs"))
+        }
+        (RuleValue::Group(_), _) => unreachable!("groups are written by navidrome_criteria"),
+    };
+
+    Ok(json!({ operator: { field: value } }))
+}
+
+/// The key for the conjunction and the rules under it, any that can't be
+/// translated are added to the problems.
+fn navidrome_criteria(
+    criteria: &SmartCriteria,
+    problems: &mut Vec<String>,
+) -> (&'static str, Vec<Value>) {
+    let rules = criteria
+        .rules
+        .iter()
+        .filter_map(|rule| match &rule.value {
+            RuleValue::Group(_) if rule.negated => {
+                problems.push(String::from("Navidrome can't negate a group of rules"));
+                None
+            }
+            RuleValue::Group(group) => {
+                let (key, rules) = navidrome_criteria(group, problems);
+                Some(json!({ key: rules }))
+            }
+            _ => navidrome_rule(rule).map_err(|p| problems.push(p)).ok(),
+        })
+        .collect::<Vec<Value>>();
+
+    let key = match criteria.conjunction {
+        Conjunction::All => "all",
+        Conjunction::Any => "any",
+    };
+    (key, rules)
+}
+
+fn navidrome_sort(selection: Selection) -> Option<(&'static str, bool)> {
+    match selection {
+        Selection::Random => Some(("random", false)),
+        Selection::Name => Some(("title", false)),
+        Selection::Album => Some(("album", false)),
+        Selection::Artist => Some(("artist", false)),
+        Selection::Genre => Some(("genre", false)),
+        Selection::HighestRating => Some(("rating", true)),
+        Selection::MostOftenPlayed => Some(("playcount", true)),
+        Selection::MostRecentlyPlayed => Some(("lastplayed", true)),
+        Selection::MostRecentlyAdded => Some(("dateadded", true)),
+        Selection::Unknown(_) => None,
+    }
+}
+
+/// Renders a Navidrome smart playlist, or the list of criteria that
+/// Navidrome can't express.
+pub fn to_nsp(name: &str, smart: &SmartPlaylist) -> Result<String, Vec<String>> {
+    let mut problems = Vec::new();
+    let mut nsp = Map::new();
+    nsp.insert(String::from("name"), json!(name));
+
+    let (key, rules) = match &smart.criteria {
+        Some(criteria) => navidrome_criteria(criteria, &mut problems),
+        None => ("all", Vec::new()),
+    };
+    nsp.insert(String::from(key), json!(rules));
+
+    if let Some(limit) = &smart.limit {
+        if limit.unit != LimitUnit::Items {
+            problems.push(format!("Navidrome can only limit by items, not {:?}", limit.unit));
+        }
+
+        match navidrome_sort(limit.selection) {
+            Some((sort, descending)) => {
+                let descending = descending != limit.reverse;
+                nsp.insert(String::from("sort"), json!(sort));
+                nsp.insert(
+                    String::from("order"),
+                    json!(if descending { "desc" } else { "asc" }),
+                );
+                nsp.insert(String::from("limit"), json!(limit.value));
+            }
+            None => problems.push(format!("Unknown limit selection {:?}", limit.selection)),
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(format!("{:#}\n", Value::Object(nsp)))
+    } else {
+        Err(problems)
+    }
+}
+
+/// Translates a smart playlist into the requested rule format, returning
+/// the file name and contents. Returns None for regular playlists or when
+/// the rules can't be translated, after reporting why, so the caller can
+/// fall back to a static playlist.
+pub fn translate_smart_playlist(
+    args: &ExportOptions,
+    format: SmartFormat,
+    playlist: &Playlist,
+    playlists: &[Playlist],
+) -> Option<(String, String)> {
+    let name = &playlist.name;
+    let smart = match playlist.smart.as_ref()? {
+        Ok(smart) => smart,
+        Err(err) => {
+            args.log.warn(&format!("Smart playlist {name}: {err}, writing a static playlist instead."));
+            return None;
+        }
+    };
+
+    let (extension, translated) = match format {
+        SmartFormat::Kodi => ("xsp", to_xsp(name, smart, playlists)),
+        SmartFormat::Navidrome => ("nsp", to_nsp(name, smart)),
+    };
+
+    match translated {
+        Ok(contents) => {
+            if args.verbose > 1 {
+                args.log.info(&format!("Translated smart playlist {name} to {extension}."));
+            }
+            Some(([name.as_str(), extension].join("."), contents))
+        }
+        Err(problems) => {
+            args.log.warn(&format!("Smart playlist {name} can't be translated, writing a static playlist instead:"));
+            problems
+                .iter()
+                .for_each(|problem| args.log.warn(&format!("  {problem}")));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+    use crate::smart::Limit;
+
+    fn rule(field: Field, operator: Operator, negated: bool, value: RuleValue) -> Rule {
+        Rule {
+            field,
+            operator,
+            negated,
+            value,
+        }
+    }
+
+    fn smart(conjunction: Conjunction, rules: Vec<Rule>, limit: Option<Limit>) -> SmartPlaylist {
+        SmartPlaylist {
+            live_updating: true,
+            match_checked_only: true,
+            limit,
+            criteria: Some(SmartCriteria { conjunction, rules }),
+        }
+    }
+
+    fn top_rated() -> SmartPlaylist {
+        smart(
+            Conjunction::All,
+            vec![
+                rule(Field::Genre, Operator::Contains, false, RuleValue::Text(String::from("Rock & Roll"))),
+                rule(Field::Year, Operator::InRange, false, RuleValue::Integer(1970, 1979)),
+                rule(
+                    Field::DateAdded,
+                    Operator::InTheLast,
+                    false,
+                    RuleValue::Within(Duration::from_secs(14 * SECONDS_PER_DAY)),
+                ),
+            ],
+            Some(Limit {
+                value: 25,
+                unit: LimitUnit::Items,
+                selection: Selection::HighestRating,
+                reverse: false,
+            }),
+        )
+    }
+
+    #[test]
+    fn test_to_xsp() {
+        let expected = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\" ?>\n\
+            <smartplaylist type=\"songs\">\n    \
+            <name>Top &amp; Rated</name>\n    \
+            <match>all</match>\n    \
+            <rule field=\"genre\" operator=\"contains\">\n        <value>Rock &amp; Roll</value>\n    </rule>\n    \
+            <rule field=\"year\" operator=\"greaterthan\">\n        <value>1969</value>\n    </rule>\n    \
+            <rule field=\"year\" operator=\"lessthan\">\n        <value>1980</value>\n    </rule>\n    \
+            <rule field=\"dateadded\" operator=\"inthelast\">\n        <value>14 days</value>\n    </rule>\n    \
+            <limit>25</limit>\n    \
+            <order direction=\"descending\">userrating</order>\n\
+            </smartplaylist>\n";
+        assert_eq!(to_xsp("Top & Rated", &top_rated(), &[]), Ok(String::from(expected)));
+    }
+
+    #[test]
+    fn test_to_xsp_date_range() {
+        // 2020-01-10 to 2020-01-20, both days included.
+        let low = UNIX_EPOCH + Duration::from_secs(18_271 * SECONDS_PER_DAY);
+        let high = UNIX_EPOCH + Duration::from_secs(18_281 * SECONDS_PER_DAY);
+        let range = rule(Field::DateAdded, Operator::InRange, false, RuleValue::Date(low, high));
+        let rules = kodi_rule(&range, Conjunction::All, &HashMap::new()).unwrap();
+        assert_eq!(
+            rules,
+            vec![
+                "    <rule field=\"dateadded\" operator=\"after\">\n        <value>2020-01-09</value>\n    </rule>",
+                "    <rule field=\"dateadded\" operator=\"before\">\n        <value>2020-01-21</value>\n    </rule>",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_xsp_unsupported() {
+        let playlist = smart(
+            Conjunction::Any,
+            vec![
+                rule(Field::Year, Operator::InRange, false, RuleValue::Integer(1970, 1979)),
+                rule(Field::Kind, Operator::Is, false, RuleValue::Text(String::from("MPEG"))),
+            ],
+            None,
+        );
+
+        let problems = to_xsp("foo", &playlist, &[]).unwrap_err();
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[1], "Kodi has no equivalent of the Kind field");
+    }
+
+    #[test]
+    fn test_to_nsp() {
+        let parsed: Value = serde_json::from_str(&to_nsp("Top Rated", &top_rated()).unwrap()).unwrap();
+        assert_eq!(
+            parsed,
+            json!({
+                "name": "Top Rated",
+                "all": [
+                    { "contains": { "genre": "Rock & Roll" } },
+                    { "inTheRange": { "year": [1970, 1979] } },
+                    { "inTheLast": { "dateadded": 14 } },
+                ],
+                "sort": "rating",
+                "order": "desc",
+                "limit": 25,
+            })
+        );
+    }
+
+    #[test]
+    fn test_nested_rules() {
+        let genres = RuleValue::Group(SmartCriteria {
+            conjunction: Conjunction::Any,
+            rules: vec![
+                rule(Field::Genre, Operator::Is, false, RuleValue::Text(String::from("Rock"))),
+                rule(Field::Genre, Operator::Is, false, RuleValue::Text(String::from("Metal"))),
+            ],
+        });
+        let nested = smart(
+            Conjunction::All,
+            vec![
+                rule(Field::Group, Operator::Is, false, genres.clone()),
+                rule(Field::Year, Operator::GreaterThan, false, RuleValue::Integer(1979, 0)),
+            ],
+            None,
+        );
+
+        let nsp: Value = serde_json::from_str(&to_nsp("Heavy", &nested).unwrap()).unwrap();
+        assert_eq!(
+            nsp["all"],
+            json!([
+                { "any": [{ "is": { "genre": "Rock" } }, { "is": { "genre": "Metal" } }] },
+                { "gt": { "year": 1979 } }
+            ])
+        );
+
+        assert_eq!(
+            to_xsp("Heavy", &nested, &[]).unwrap_err(),
+            vec![String::from("Kodi has no nested groups of rules")]
+        );
+
+        let negated = smart(
+            Conjunction::All,
+            vec![rule(Field::Group, Operator::Is, true, genres)],
+            None,
+        );
+        assert_eq!(
+            to_nsp("Not heavy", &negated).unwrap_err(),
+            vec![String::from("Navidrome can't negate a group of rules")]
+        );
+    }
+
+    #[test]
+    fn test_to_nsp_unsupported() {
+        let playlist = smart(
+            Conjunction::All,
+            vec![rule(Field::Name, Operator::StartsWith, true, RuleValue::Text(String::from("a")))],
+            Some(Limit {
+                value: 60,
+                unit: LimitUnit::Minutes,
+                selection: Selection::Random,
+                reverse: false,
+            }),
+        );
+
+        let problems = to_nsp("foo", &playlist).unwrap_err();
+        assert_eq!(
+            problems,
+            vec![
+                String::from("Navidrome has no equivalent of not StartsWith on Name"),
+                String::from("Navidrome can only limit by items, not Minutes"),
+            ]
+        );
+    }
+}
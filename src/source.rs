@@ -0,0 +1,38 @@
+//! source
+//!
+//! The interface library exports are read through. Each source turns its
+//! own format into the common [`Library`] model so the writers don't need
+//! to know where the playlists came from.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use crate::library::Library;
+
+#[derive(Debug)]
+pub struct SourceError(pub String);
+
+impl Error for SourceError {}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A kind of library export, e.g. the iTunes® library XML.
+///
+/// Playlists are built from `Playlist::default()` with their fields set.
+/// Smart playlists can only be given as the rules iTunes® encodes, with
+/// `Playlist::set_smart_rules`, any other source's smart playlists are
+/// written as the tracks they hold.
+pub trait LibrarySource {
+    /// What the source reads, for messages.
+    fn name(&self) -> &'static str;
+
+    fn load(&self, path: &Path) -> Result<Library, SourceError>;
+}
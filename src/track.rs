@@ -5,50 +5,181 @@
 //! author: jasmith79
 //! license: MIT
 //! copyright: 2023
-use crate::path_utils::{deserialize_and_normalize, deserialize_path};
-use serde::Deserialize;
+use crate::path_utils::{deserialize_path, deserialize_string, serialize_path};
+use plist::Date;
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
 static UNTITLED_TRACK: &str = "Untitled Track";
 static UNKNOWN_ARTIST: &str = "Unknown Artist";
 
-#[derive(Deserialize)]
+#[derive(Default, Deserialize, Serialize)]
 pub struct Track {
-    // May need this for xspf at some point
+    // The Track ID is only stable within a single export so the xspf
+    // identifier uses the Persistent ID instead.
     // #[serde(rename = "Track ID")]
     // track_id: i32,
-    #[serde(rename = "Name")]
-    #[serde(deserialize_with = "deserialize_and_normalize")]
+    #[serde(rename(deserialize = "Persistent ID"))]
+    #[serde(default)]
+    pub persistent_id: Option<String>,
+
+    #[serde(rename(deserialize = "Name"))]
+    #[serde(deserialize_with = "deserialize_string")]
     #[serde(default)]
     pub name: Option<String>,
 
-    #[serde(rename = "Location")]
+    #[serde(rename(deserialize = "Location"))]
     #[serde(deserialize_with = "deserialize_path")]
+    #[serde(serialize_with = "serialize_path")]
     #[serde(default)]
     pub location: Option<PathBuf>,
 
-    #[serde(rename = "Total Time")]
+    #[serde(rename(deserialize = "Total Time"))]
     #[serde(default)]
     pub duration: i32,
 
-    // May need this for xspf at some point
-    // #[serde(rename = "Album")]
-    // #[serde(default)]
-    // album: Option<String>,
-    #[serde(rename = "Album Artist")]
-    #[serde(deserialize_with = "deserialize_and_normalize")]
+    #[serde(rename(deserialize = "Album"))]
+    #[serde(deserialize_with = "deserialize_string")]
+    #[serde(default)]
+    pub album: Option<String>,
+
+    #[serde(rename(deserialize = "Genre"))]
+    #[serde(deserialize_with = "deserialize_string")]
+    #[serde(default)]
+    pub genre: Option<String>,
+
+    #[serde(rename(deserialize = "Kind"))]
+    #[serde(default)]
+    pub kind: Option<String>,
+
+    #[serde(rename(deserialize = "Year"))]
+    #[serde(default)]
+    pub year: Option<i32>,
+
+    #[serde(rename(deserialize = "Track Number"))]
+    #[serde(default)]
+    pub track_number: Option<i32>,
+
+    #[serde(rename(deserialize = "Track Count"))]
+    #[serde(default)]
+    pub track_count: Option<i32>,
+
+    #[serde(rename(deserialize = "Disc Number"))]
+    #[serde(default)]
+    pub disc_number: Option<i32>,
+
+    #[serde(rename(deserialize = "Disc Count"))]
+    #[serde(default)]
+    pub disc_count: Option<i32>,
+
+    #[serde(rename(deserialize = "Album Artist"))]
+    #[serde(deserialize_with = "deserialize_string")]
     #[serde(default)]
     pub album_artist: Option<String>,
 
-    #[serde(rename = "Artist")]
-    #[serde(deserialize_with = "deserialize_and_normalize")]
+    #[serde(rename(deserialize = "Artist"))]
+    #[serde(deserialize_with = "deserialize_string")]
     #[serde(default)]
     pub artist: Option<String>,
 
-    #[serde(rename = "Composer")]
-    #[serde(deserialize_with = "deserialize_and_normalize")]
+    #[serde(rename(deserialize = "Composer"))]
+    #[serde(deserialize_with = "deserialize_string")]
     #[serde(default)]
     pub composer: Option<String>,
+
+    #[serde(rename(deserialize = "Compilation"))]
+    #[serde(default)]
+    pub compilation: bool,
+
+    #[serde(rename(deserialize = "Grouping"))]
+    #[serde(deserialize_with = "deserialize_string")]
+    #[serde(default)]
+    pub grouping: Option<String>,
+
+    #[serde(rename(deserialize = "Comments"))]
+    #[serde(deserialize_with = "deserialize_string")]
+    #[serde(default)]
+    pub comments: Option<String>,
+
+    #[serde(rename(deserialize = "BPM"))]
+    #[serde(default)]
+    pub bpm: Option<i32>,
+
+    // iTunes stores star ratings as 0-100 in steps of 20.
+    #[serde(rename(deserialize = "Rating"))]
+    #[serde(default)]
+    pub rating: Option<i32>,
+
+    #[serde(rename(deserialize = "Loved"))]
+    #[serde(default)]
+    pub loved: bool,
+
+    #[serde(rename(deserialize = "Play Count"))]
+    #[serde(default)]
+    pub play_count: Option<i32>,
+
+    #[serde(rename(deserialize = "Skip Count"))]
+    #[serde(default)]
+    pub skip_count: Option<i32>,
+
+    #[serde(rename(deserialize = "Date Added"))]
+    #[serde(default)]
+    pub date_added: Option<Date>,
+
+    #[serde(rename(deserialize = "Play Date UTC"))]
+    #[serde(default)]
+    pub play_date_utc: Option<Date>,
+
+    #[serde(rename(deserialize = "Skip Date"))]
+    #[serde(default)]
+    pub skip_date: Option<Date>,
+
+    #[serde(rename(deserialize = "Date Modified"))]
+    #[serde(default)]
+    pub date_modified: Option<Date>,
+
+    // Unchecked in the iTunes UI.
+    #[serde(rename(deserialize = "Disabled"))]
+    #[serde(default)]
+    pub disabled: bool,
+
+    #[serde(rename(deserialize = "Bit Rate"))]
+    #[serde(default)]
+    pub bit_rate: Option<i32>,
+
+    #[serde(rename(deserialize = "Sample Rate"))]
+    #[serde(default)]
+    pub sample_rate: Option<i32>,
+
+    // In bytes, can be well over i32::MAX for videos.
+    #[serde(rename(deserialize = "Size"))]
+    #[serde(default)]
+    pub size: Option<u64>,
+
+    #[serde(rename(deserialize = "Sort Name"))]
+    #[serde(deserialize_with = "deserialize_string")]
+    #[serde(default)]
+    pub sort_name: Option<String>,
+
+    #[serde(rename(deserialize = "Sort Album"))]
+    #[serde(deserialize_with = "deserialize_string")]
+    #[serde(default)]
+    pub sort_album: Option<String>,
+
+    #[serde(rename(deserialize = "Sort Artist"))]
+    #[serde(deserialize_with = "deserialize_string")]
+    #[serde(default)]
+    pub sort_artist: Option<String>,
+
+    #[serde(rename(deserialize = "Sort Album Artist"))]
+    #[serde(deserialize_with = "deserialize_string")]
+    #[serde(default)]
+    pub sort_album_artist: Option<String>,
+
+    #[serde(rename(deserialize = "Sort Composer"))]
+    #[serde(deserialize_with = "deserialize_string")]
+    #[serde(default)]
+    pub sort_composer: Option<String>,
 }
 
 impl Track {
@@ -78,6 +209,7 @@ mod tests {
             artist: Some(String::from("bob")),
             album_artist: Some(String::from("steve")),
             composer: Some(String::from("jane")),
+            ..Default::default()
         };
 
         assert_eq!(track.get_artist(), "bob");
@@ -92,6 +224,7 @@ mod tests {
             artist: None,
             album_artist: Some(String::from("bob")),
             composer: Some(String::from("steve")),
+            ..Default::default()
         };
 
         assert_eq!(track.get_artist(), "bob");
@@ -106,6 +239,7 @@ mod tests {
             artist: None,
             album_artist: None,
             composer: Some(String::from("bob")),
+            ..Default::default()
         };
 
         assert_eq!(track.get_artist(), "bob");
@@ -120,6 +254,7 @@ mod tests {
             artist: None,
             album_artist: None,
             composer: None,
+            ..Default::default()
         };
 
         assert_eq!(track.get_artist(), "Unknown Artist");
@@ -134,6 +269,7 @@ mod tests {
             artist: None,
             album_artist: None,
             composer: None,
+            ..Default::default()
         };
 
         assert_eq!(track.get_name(), "Favorite Song");
@@ -148,8 +284,70 @@ mod tests {
             artist: None,
             album_artist: None,
             composer: None,
+            ..Default::default()
         };
 
         assert_eq!(track.get_name(), "Untitled Track");
     }
+
+    #[test]
+    fn test_deserialize_full_metadata() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple Computer//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Track ID</key><integer>1234</integer>
+    <key>Name</key><string>Favorite Song</string>
+    <key>Artist</key><string>dude</string>
+    <key>Album</key><string>Greatest Hits</string>
+    <key>Genre</key><string>Rock</string>
+    <key>Kind</key><string>Apple Lossless audio file</string>
+    <key>Size</key><integer>4294967296</integer>
+    <key>Total Time</key><integer>3000</integer>
+    <key>Disc Number</key><integer>1</integer>
+    <key>Disc Count</key><integer>2</integer>
+    <key>Track Number</key><integer>3</integer>
+    <key>Track Count</key><integer>12</integer>
+    <key>Year</key><integer>1999</integer>
+    <key>BPM</key><integer>120</integer>
+    <key>Date Added</key><date>2020-01-02T03:04:05Z</date>
+    <key>Bit Rate</key><integer>256</integer>
+    <key>Sample Rate</key><integer>44100</integer>
+    <key>Comments</key><string>nice</string>
+    <key>Play Count</key><integer>7</integer>
+    <key>Play Date UTC</key><date>2021-01-02T03:04:05Z</date>
+    <key>Skip Count</key><integer>2</integer>
+    <key>Rating</key><integer>80</integer>
+    <key>Loved</key><true/>
+    <key>Compilation</key><true/>
+    <key>Sort Name</key><string>Favorite Song</string>
+    <key>Sort Album</key><string>Greatest Hits</string>
+    <key>Sort Artist</key><string>dude</string>
+    <key>Sort Album Artist</key><string>dude</string>
+    <key>Sort Composer</key><string>dude</string>
+    <key>Persistent ID</key><string>ABC123</string>
+    <key>Location</key><string>file:///foo/bar.m4a</string>
+</dict>
+</plist>"#;
+
+        let track: Track = plist::from_bytes(xml.as_bytes()).unwrap();
+        assert_eq!(track.get_name(), "Favorite Song");
+        assert_eq!(track.album.as_deref(), Some("Greatest Hits"));
+        assert_eq!(track.genre.as_deref(), Some("Rock"));
+        assert_eq!(track.kind.as_deref(), Some("Apple Lossless audio file"));
+        assert_eq!(track.size, Some(4294967296));
+        assert_eq!((track.disc_number, track.disc_count), (Some(1), Some(2)));
+        assert_eq!((track.track_number, track.track_count), (Some(3), Some(12)));
+        assert_eq!((track.year, track.bpm), (Some(1999), Some(120)));
+        assert_eq!((track.bit_rate, track.sample_rate), (Some(256), Some(44100)));
+        assert_eq!(track.comments.as_deref(), Some("nice"));
+        assert_eq!((track.play_count, track.skip_count), (Some(7), Some(2)));
+        assert_eq!(track.rating, Some(80));
+        assert!(track.loved && track.compilation);
+        assert_eq!(track.date_added.unwrap().to_xml_format(), "2020-01-02T03:04:05Z");
+        assert_eq!(track.play_date_utc.unwrap().to_xml_format(), "2021-01-02T03:04:05Z");
+        assert_eq!(track.sort_composer.as_deref(), Some("dude"));
+        assert_eq!(track.persistent_id.as_deref(), Some("ABC123"));
+        assert_eq!(track.location, Some(PathBuf::from("/foo/bar.m4a")));
+    }
 }
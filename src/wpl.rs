@@ -0,0 +1,145 @@
+//! wpl
+//!
+//! Contains functions pertaining to the creation of the SMIL based Windows
+//! Media Player playlist format.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use crate::export::ExportOptions;
+use crate::library::Playlist;
+use crate::path_utils::{format_location, resolve_path, verify_path, LocationContext};
+use crate::track::Track;
+use crate::writer::PlaylistWriter;
+use crate::xml::{escape_xml, is_xml_comment, xml_comment};
+
+fn to_wpl_track(ctx: &LocationContext, args: &ExportOptions, track: &Track) -> Option<String> {
+    let name = track.get_name();
+    if args.verbose > 3 {
+        args.log.info(&format!("Converting track {name}"));
+    }
+
+    let path = resolve_path(ctx, args, track)?;
+    let missing = verify_path(ctx, args, &path)?;
+    let location = format_location(ctx, args, &path)?;
+    if missing {
+        return Some(format!(
+            "            {}",
+            xml_comment(&format!("Missing: {location}"))
+        ));
+    }
+
+    Some(format!(
+        "            <media src=\"{}\"/>",
+        escape_xml(&location)
+    ))
+}
+
+/// Windows Media Player's SMIL based playlists.
+pub struct WplWriter;
+
+impl PlaylistWriter for WplWriter {
+    fn name(&self) -> &'static str {
+        "wpl"
+    }
+
+    fn extension(&self) -> &str {
+        "wpl"
+    }
+
+    fn header(&self, options: &ExportOptions, playlist: &Playlist, entries: &[String]) -> String {
+        format!(
+            "<?wpl version=\"1.0\"?>\n\
+            <smil>\n    \
+            <head>\n        \
+            <meta name=\"Generator\" content=\"playlistrs -- {}\"/>\n        \
+            <meta name=\"ItemCount\" content=\"{}\"/>\n        \
+            <title>{}</title>\n    \
+            </head>\n    \
+            <body>\n        \
+            <seq>\n",
+            env!("CARGO_PKG_VERSION"),
+            entries.iter().filter(|entry| !is_xml_comment(entry)).count(),
+            escape_xml(&options.normalization.apply(&playlist.name)),
+        )
+    }
+
+    fn entry(
+        &self,
+        ctx: &LocationContext,
+        options: &ExportOptions,
+        track: &Track,
+        _number: usize,
+    ) -> Option<String> {
+        to_wpl_track(ctx, options, track)
+    }
+
+    fn footer(&self, _options: &ExportOptions, _playlist: &Playlist, _entries: &[String]) -> String {
+        String::from("\n        </seq>\n    </body>\n</smil>\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use super::*;
+    use crate::library::PlaylistItem;
+
+    #[test]
+    fn test_to_wpl_track_windows() {
+        let track = Track {
+            name: Some(String::from("Favorite Song")),
+            location: Some(PathBuf::from("/foo/Artist/Album & More/bar.m4a")),
+            ..Default::default()
+        };
+
+        let args = ExportOptions {
+            music_path: Some(PathBuf::from("/Music")),
+            windows_drive: Some('E'),
+            ..Default::default()
+        };
+
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let result = to_wpl_track(&ctx, &args, &track);
+        assert_eq!(
+            result,
+            Some(String::from(
+                "            <media src=\"E:\\Music\\Artist\\Album &amp; More\\bar.m4a\"/>"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_wpl_writer() {
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let playlist = Playlist {
+            name: String::from("foobar"),
+            playlist_items: vec![PlaylistItem { track_id: String::from("1") }, PlaylistItem { track_id: String::from("2") }],
+            ..Default::default()
+        };
+
+        let tracks = HashMap::from([
+            (String::from("1"), Track {
+                name: Some(String::from("Favorite Song")),
+                location: Some(PathBuf::from("/foo/bar")),
+                ..Default::default()
+            }),
+            (String::from("2"), Track {
+                name: Some(String::from("Least Favorite Song")),
+                location: None,
+                ..Default::default()
+            }),
+        ]);
+
+        let args = ExportOptions::default();
+        let (file_name, contents) = (WplWriter.file_name(&playlist), WplWriter.render(&ctx, &args, &tracks, &playlist));
+        assert_eq!(file_name, "foobar.wpl");
+        assert!(contents.starts_with("<?wpl version=\"1.0\"?>\n<smil>\n"));
+        assert!(contents.contains("<meta name=\"ItemCount\" content=\"1\"/>"));
+        assert!(contents.contains("<title>foobar</title>"));
+        assert!(contents.contains("        <seq>\n            <media src=\"/foo/bar\"/>\n        </seq>\n"));
+    }
+}
@@ -0,0 +1,308 @@
+//! writer
+//!
+//! The interface every playlist format is written through and the registry
+//! of formats `--format` chooses from. Library users can register their
+//! own writers alongside, or in place of, the built in ones.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::collections::HashMap;
+
+use crate::export::ExportOptions;
+use crate::json::{JsonWriter, JspfWriter};
+use crate::library::Playlist;
+use crate::m3u::{M3u8Writer, M3uWriter};
+use crate::path_utils::LocationContext;
+use crate::pls::PlsWriter;
+use crate::track::Track;
+use crate::wpl::WplWriter;
+use crate::xspf::XspfWriter;
+
+static LATIN1_REPLACEMENT: u8 = b'?';
+
+/// How the rendered playlist is encoded when it's written to disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    /// ISO-8859-1, for players that predate UTF-8. Characters it can't
+    /// represent are written as ?.
+    Latin1,
+}
+
+impl Encoding {
+    pub fn encode(self, text: String) -> Vec<u8> {
+        match self {
+            Encoding::Utf8 => text.into_bytes(),
+            Encoding::Latin1 => text
+                .chars()
+                .map(|c| u8::try_from(c).unwrap_or(LATIN1_REPLACEMENT))
+                .collect(),
+        }
+    }
+}
+
+/// A playlist format. A playlist is written as the header, then an entry
+/// for each of its tracks that can be located, then the footer, so most
+/// formats only need to say how to write a single track. Formats that are
+/// written as a single document, like JSON, override `render` instead and
+/// leave the rest alone.
+pub trait PlaylistWriter {
+    /// The name `--format` selects the writer by.
+    fn name(&self) -> &'static str;
+
+    /// The file extension, without the dot.
+    fn extension(&self) -> &str;
+
+    fn encoding(&self) -> Encoding {
+        Encoding::Utf8
+    }
+
+    /// Written before the entries, which have already been rendered.
+    fn header(&self, _options: &ExportOptions, _playlist: &Playlist, _entries: &[String]) -> String {
+        String::new()
+    }
+
+    /// The entry for a track, numbered from 1 among the entries written so
+    /// far. None leaves the track out, which is all the default does.
+    fn entry(
+        &self,
+        _ctx: &LocationContext,
+        _options: &ExportOptions,
+        _track: &Track,
+        _number: usize,
+    ) -> Option<String> {
+        None
+    }
+
+    /// Joins the entries together.
+    fn separator(&self) -> &str {
+        "\n"
+    }
+
+    /// Written after the entries.
+    fn footer(&self, _options: &ExportOptions, _playlist: &Playlist, _entries: &[String]) -> String {
+        String::new()
+    }
+
+    fn file_name(&self, playlist: &Playlist) -> String {
+        [&playlist.name, self.extension()].join(".")
+    }
+
+    fn render(
+        &self,
+        ctx: &LocationContext,
+        options: &ExportOptions,
+        tracks: &HashMap<String, Track>,
+        playlist: &Playlist,
+    ) -> String {
+        let mut entries = Vec::new();
+        for item in &playlist.playlist_items {
+            let entry = tracks
+                .get(&item.track_id)
+                .and_then(|track| self.entry(ctx, options, track, entries.len() + 1));
+
+            entries.extend(entry);
+        }
+
+        format!(
+            "{}{}{}",
+            self.header(options, playlist, &entries),
+            entries.join(self.separator()),
+            self.footer(options, playlist, &entries)
+        )
+    }
+}
+
+/// The writers to choose from by name.
+pub struct WriterRegistry {
+    writers: Vec<Box<dyn PlaylistWriter>>,
+}
+
+impl WriterRegistry {
+    /// A registry without any writers.
+    pub fn new() -> WriterRegistry {
+        WriterRegistry {
+            writers: Vec::new(),
+        }
+    }
+
+    /// Adds a writer, replacing any already registered with the same name.
+    pub fn register(&mut self, writer: Box<dyn PlaylistWriter>) {
+        match self.writers.iter().position(|w| w.name() == writer.name()) {
+            Some(index) => self.writers[index] = writer,
+            None => self.writers.push(writer),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn PlaylistWriter> {
+        self.writers
+            .iter()
+            .find(|writer| writer.name() == name)
+            .map(|writer| writer.as_ref())
+    }
+
+    /// The registered names, in the order they were registered.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.writers.iter().map(|writer| writer.name()).collect()
+    }
+}
+
+/// Registers all of the built in formats.
+impl Default for WriterRegistry {
+    fn default() -> WriterRegistry {
+        let mut registry = WriterRegistry::new();
+        registry.register(Box::new(M3uWriter));
+        registry.register(Box::new(M3u8Writer));
+        registry.register(Box::new(XspfWriter));
+        registry.register(Box::new(PlsWriter));
+        registry.register(Box::new(WplWriter));
+        registry.register(Box::new(JspfWriter));
+        registry.register(Box::new(JsonWriter));
+        registry
+    }
+}
+
+/// Renders a playlist, returning its file name and encoded contents.
+/// Tracks that can't be located are left out.
+pub fn render_playlist(
+    writer: &dyn PlaylistWriter,
+    ctx: &LocationContext,
+    options: &ExportOptions,
+    tracks: &HashMap<String, Track>,
+    playlist: &Playlist,
+) -> (String, Vec<u8>) {
+    let contents = writer.render(ctx, options, tracks, playlist);
+    (writer.file_name(playlist), writer.encoding().encode(contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::library::PlaylistItem;
+
+    struct TitlesWriter;
+
+    impl PlaylistWriter for TitlesWriter {
+        fn name(&self) -> &'static str {
+            "m3u"
+        }
+
+        fn extension(&self) -> &str {
+            "txt"
+        }
+
+        fn encoding(&self) -> Encoding {
+            Encoding::Latin1
+        }
+
+        fn header(&self, _options: &ExportOptions, playlist: &Playlist, entries: &[String]) -> String {
+            format!("{} ({})\n", playlist.name, entries.len())
+        }
+
+        fn entry(
+            &self,
+            _ctx: &LocationContext,
+            _options: &ExportOptions,
+            track: &Track,
+            number: usize,
+        ) -> Option<String> {
+            track.location.as_ref()?;
+            Some(format!("{number}. {}", track.get_name()))
+        }
+
+        fn footer(&self, _options: &ExportOptions, _playlist: &Playlist, _entries: &[String]) -> String {
+            String::from("\n")
+        }
+    }
+
+    /// Written as a whole, so it has no entries.
+    struct CountWriter;
+
+    impl PlaylistWriter for CountWriter {
+        fn name(&self) -> &'static str {
+            "count"
+        }
+
+        fn extension(&self) -> &str {
+            "count"
+        }
+
+        fn render(
+            &self,
+            _ctx: &LocationContext,
+            _options: &ExportOptions,
+            _tracks: &HashMap<String, Track>,
+            playlist: &Playlist,
+        ) -> String {
+            playlist.playlist_items.len().to_string()
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(Encoding::Utf8.encode(String::from("Café")), "Café".as_bytes());
+        assert_eq!(Encoding::Latin1.encode(String::from("Café ♫")), b"Caf\xe9 ?");
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = WriterRegistry::default();
+        assert_eq!(registry.names(), ["m3u", "m3u8", "xspf", "pls", "wpl", "jspf", "json"]);
+        assert_eq!(registry.get("m3u8").map(|writer| writer.extension()), Some("m3u8"));
+        assert!(registry.get("txt").is_none());
+
+        registry.register(Box::new(TitlesWriter));
+        assert_eq!(registry.names().len(), 7);
+        assert_eq!(registry.get("m3u").map(|writer| writer.extension()), Some("txt"));
+    }
+
+    #[test]
+    fn test_render_playlist() {
+        let playlist = Playlist {
+            name: String::from("Mix"),
+            playlist_items: vec![
+                PlaylistItem { track_id: String::from("1") },
+                PlaylistItem { track_id: String::from("2") },
+                PlaylistItem { track_id: String::from("3") },
+            ],
+            ..Default::default()
+        };
+
+        let tracks = HashMap::from([
+            (String::from("1"), Track {
+                name: Some(String::from("Café")),
+                location: Some(PathBuf::from("/a.mp3")),
+                ..Default::default()
+            }),
+            (String::from("2"), Track {
+                name: Some(String::from("Nowhere")),
+                ..Default::default()
+            }),
+            (String::from("3"), Track {
+                name: Some(String::from("Last")),
+                location: Some(PathBuf::from("/b.mp3")),
+                ..Default::default()
+            }),
+        ]);
+
+        let ctx = LocationContext::new(&[], Path::new("/"));
+        let (file_name, contents) = render_playlist(
+            &TitlesWriter,
+            &ctx,
+            &ExportOptions::default(),
+            &tracks,
+            &playlist,
+        );
+        assert_eq!(file_name, "Mix.txt");
+        assert_eq!(contents, b"Mix (2)\n1. Caf\xe9\n2. Last\n");
+
+        let (file_name, contents) =
+            render_playlist(&CountWriter, &ctx, &ExportOptions::default(), &tracks, &playlist);
+        assert_eq!(file_name, "Mix.count");
+        assert_eq!(contents, b"3");
+    }
+}
@@ -0,0 +1,66 @@
+//! xml
+//!
+//! Small helpers for the XML based playlist formats.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use std::borrow::Cow;
+
+/// Escapes the five XML special characters for use in text content and
+/// attribute values.
+pub fn escape_xml(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    Cow::Owned(escaped)
+}
+
+/// Wraps text in an XML comment, breaking up any -- which comments can't
+/// contain.
+pub fn xml_comment(text: &str) -> String {
+    let mut text = text.to_string();
+    while text.contains("--") {
+        text = text.replace("--", "- -");
+    }
+
+    format!("<!-- {text} -->")
+}
+
+/// Whether a line of output is an XML comment.
+pub fn is_xml_comment(line: &str) -> bool {
+    line.trim_start().starts_with("<!--")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(escape_xml("Simon & Garfunkel"), "Simon &amp; Garfunkel");
+        assert_eq!(escape_xml("<\"'>"), "&lt;&quot;&apos;&gt;");
+        assert!(matches!(escape_xml("plain"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_xml_comment() {
+        assert_eq!(xml_comment("Missing: /a/b.mp3"), "<!-- Missing: /a/b.mp3 -->");
+        assert_eq!(xml_comment("a---b-"), "<!-- a- - -b- -->");
+        assert!(is_xml_comment("    <!-- foo -->"));
+        assert!(!is_xml_comment("<media src=\"foo\"/>"));
+    }
+}
@@ -0,0 +1,195 @@
+//! xspf
+//!
+//! Contains functions pertaining to the creation of the XSPF (XML Shareable
+//! Playlist Format) playlist format.
+//!
+//! @author jasmith79
+//! @license MIT
+//! @copyright 2023
+use crate::export::ExportOptions;
+use crate::library::Playlist;
+use crate::path_utils::{resolve_path, to_uri, verify_path, LocationContext};
+use crate::track::Track;
+use crate::writer::PlaylistWriter;
+use crate::xml::{escape_xml, xml_comment};
+
+fn to_xspf_track(ctx: &LocationContext, args: &ExportOptions, track: &Track) -> Option<String> {
+    let name = args.normalization.apply(track.get_name());
+    if args.verbose > 3 {
+        args.log.info(&format!("Converting track {name}"));
+    }
+
+    // XSPF locations are always URIs, so --use-file-url is implied unless
+    // --relative asks for a relative reference.
+    let path = resolve_path(ctx, args, track)?;
+    let missing = verify_path(ctx, args, &path)?;
+    let location = to_uri(ctx, args, &path)?;
+    if missing {
+        return Some(format!("    {}", xml_comment(&format!("Missing: {location}"))));
+    }
+
+    let mut elements = vec![format!(
+        "      <location>{}</location>",
+        escape_xml(&location)
+    )];
+
+    if let Some(id) = &track.persistent_id {
+        elements.push(format!(
+            "      <identifier>urn:itunes:persistent-id:{}</identifier>",
+            escape_xml(id)
+        ));
+    }
+
+    elements.push(format!("      <title>{}</title>", escape_xml(&name)));
+    elements.push(format!(
+        "      <creator>{}</creator>",
+        escape_xml(&args.normalization.apply(track.get_artist()))
+    ));
+
+    if let Some(album) = &track.album {
+        elements.push(format!("      <album>{}</album>", escape_xml(&args.normalization.apply(album))));
+    }
+
+    if let Some(track_number) = track.track_number {
+        elements.push(format!("      <trackNum>{track_number}</trackNum>"));
+    }
+
+    // Unlike m3u, XSPF durations are in milliseconds just like iTunes.
+    elements.push(format!("      <duration>{}</duration>", track.duration));
+
+    Some(format!(
+        "    <track>\n{}\n    </track>",
+        elements.join("\n")
+    ))
+}
+
+/// XML Shareable Playlist Format.
+pub struct XspfWriter;
+
+impl PlaylistWriter for XspfWriter {
+    fn name(&self) -> &'static str {
+        "xspf"
+    }
+
+    fn extension(&self) -> &str {
+        "xspf"
+    }
+
+    fn header(&self, options: &ExportOptions, playlist: &Playlist, _entries: &[String]) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  \
+            <title>{}</title>\n  \
+            <trackList>\n",
+            escape_xml(&options.normalization.apply(&playlist.name))
+        )
+    }
+
+    fn entry(
+        &self,
+        ctx: &LocationContext,
+        options: &ExportOptions,
+        track: &Track,
+        _number: usize,
+    ) -> Option<String> {
+        to_xspf_track(ctx, options, track)
+    }
+
+    fn footer(&self, _options: &ExportOptions, _playlist: &Playlist, _entries: &[String]) -> String {
+        String::from("\n  </trackList>\n</playlist>\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use super::*;
+    use crate::library::PlaylistItem;
+
+    #[test]
+    fn test_to_xspf_track() {
+        let track = Track {
+            persistent_id: Some(String::from("ABC123")),
+            name: Some(String::from("Cecilia")),
+            location: Some(PathBuf::from("/foo/bar")),
+            duration: 3000,
+            artist: Some(String::from("Simon & Garfunkel")),
+            album: Some(String::from("Bridge Over Troubled Water")),
+            track_number: Some(4),
+            ..Default::default()
+        };
+
+        let args = ExportOptions {
+            music_path: Some(PathBuf::from("/baz")),
+            ..Default::default()
+        };
+
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let expected = "    <track>\n      \
+            <location>file:///baz/bar</location>\n      \
+            <identifier>urn:itunes:persistent-id:ABC123</identifier>\n      \
+            <title>Cecilia</title>\n      \
+            <creator>Simon &amp; Garfunkel</creator>\n      \
+            <album>Bridge Over Troubled Water</album>\n      \
+            <trackNum>4</trackNum>\n      \
+            <duration>3000</duration>\n    \
+            </track>";
+        assert_eq!(to_xspf_track(&ctx, &args, &track), Some(String::from(expected)));
+    }
+
+    #[test]
+    fn test_to_xspf_track_relative() {
+        let track = Track {
+            location: Some(PathBuf::from("/music/Simon & Garfunkel/04 Cecilia.m4a")),
+            ..Default::default()
+        };
+
+        let args = ExportOptions {
+            relative: true,
+            ..Default::default()
+        };
+
+        let ctx = LocationContext::new(&[], Path::new("/music/playlists"));
+        let entry = to_xspf_track(&ctx, &args, &track).unwrap();
+        assert!(entry.contains(
+            "<location>../Simon%20&amp;%20Garfunkel/04%20Cecilia.m4a</location>"
+        ));
+    }
+
+    #[test]
+    fn test_xspf_writer() {
+        let roots = [PathBuf::from("/foo")];
+        let ctx = LocationContext::new(&roots, Path::new("/"));
+        let playlist = Playlist {
+            name: String::from("foobar"),
+            playlist_items: vec![PlaylistItem { track_id: String::from("1") }, PlaylistItem { track_id: String::from("2") }],
+            ..Default::default()
+        };
+
+        let tracks = HashMap::from([
+            (String::from("1"), Track {
+                name: Some(String::from("Favorite Song")),
+                location: Some(PathBuf::from("/foo/bar")),
+                duration: 3000,
+                artist: Some(String::from("dude")),
+                ..Default::default()
+            }),
+            (String::from("2"), Track {
+                name: Some(String::from("Least Favorite Song")),
+                location: None,
+                ..Default::default()
+            }),
+        ]);
+
+        let args = ExportOptions::default();
+        let (file_name, contents) = (XspfWriter.file_name(&playlist), XspfWriter.render(&ctx, &args, &tracks, &playlist));
+        assert_eq!(file_name, "foobar.xspf");
+        assert!(contents.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        assert!(contents.contains("  <title>foobar</title>\n  <trackList>\n    <track>\n"));
+        assert!(contents.contains("<location>file:///foo/bar</location>"));
+        assert!(!contents.contains("Least Favorite Song"));
+        assert!(contents.ends_with("    </track>\n  </trackList>\n</playlist>\n"));
+    }
+}